@@ -1,39 +1,107 @@
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs;
 use std::path::Path;
 use std::sync::Arc;
 
+use datafusion::arrow::array::{
+    Array, ArrayRef, AsArray, Float64Array, RecordBatch, StringArray, UInt64Array
+};
+use datafusion::arrow::compute::kernels::cast_utils::parse_interval_month_day_nano;
 use datafusion::arrow::csv::Writer as CsvWriter;
-use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
 use datafusion::arrow::json::LineDelimitedWriter as JsonWriter;
+use datafusion::common::{exec_err, plan_datafusion_err, plan_err, Column, DFSchema, ScalarValue, UnnestOptions};
 use datafusion::config::{ParquetOptions, TableParquetOptions};
 use datafusion::dataframe::DataFrameWriteOptions;
 use datafusion::error::Result;
 use datafusion::execution::context::SessionContext;
 use datafusion::execution::options::{AvroReadOptions, CsvReadOptions, ParquetReadOptions};
-use datafusion::prelude::{col, DataFrame};
+use datafusion::functions_aggregate::count::count_udaf;
+use datafusion::functions_aggregate::expr_fn::count;
+use datafusion::functions_window::expr_fn::row_number;
+use datafusion::logical_expr::type_coercion::binary::comparison_coercion;
+use datafusion::logical_expr::expr::WindowFunction;
+use datafusion::logical_expr::{
+    binary_expr, ExprFunctionExt, ExprSchemable, LogicalPlanBuilder, Operator, SortExpr
+};
+use datafusion::prelude::{
+    abs, cast, col, concat_ws, get_field, ident, lit, or, when, DataFrame, Expr, JoinType
+};
 use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::parquet::basic::Compression;
 use datafusion::parquet::file::properties::WriterProperties;
 
 use crate::config::*;
-use crate::plans::InputSide;
+use crate::fuzzy;
+use crate::json;
+use crate::profile;
+use crate::plans::{AsOfDirection, Keep, Severity};
+
+pub const INPUT: &str = "input";
+pub const LEFT: &str  = "left";
+pub const RIGHT: &str = "right";
+
+pub const OUTPUT: &str     = "output";
+pub const TRUE: &str       = "true";
+pub const FALSE: &str      = "false";
+pub const JOINED: &str     = "joined";
+pub const DUPLICATES: &str = "duplicates";
+pub const REJECTS: &str    = "rejects";
+pub const ADDED: &str      = "added";
+pub const REMOVED: &str    = "removed";
+pub const CHANGED: &str    = "changed";
+pub const SUMMARY: &str    = "summary";
+pub const LEFT_UNMATCHED: &str  = "left_unmatched";
+pub const RIGHT_UNMATCHED: &str = "right_unmatched";
+
+pub type Output = Vec<(&'static str, DataFrame)>;
+
+// Frames on a port are ordered by the rank of the port alias linked to,
+// then by the order of the links in the plan.
+pub type Order = (usize, usize);
 
 #[derive(Clone, Debug, Default)]
 pub struct Data {
-    pub left: Option<DataFrame>,
-    pub right: Option<DataFrame>,
+    ports: HashMap<&'static str, Vec<(Order, String, DataFrame)>>,
 }
 impl Data {
-    pub fn set(&mut self, side: InputSide, df: DataFrame)
+    pub fn add(&mut self, port: &'static str, order: Order, label: &str, df: DataFrame)
     {
-        match side {
-            InputSide::left  => self.left  = Some(df),
-            InputSide::right => self.right = Some(df),
-        }
+        self.ports.entry(port).or_default().push((order, label.into(), df))
+    }
+
+    pub fn count(&self, port: &str) -> usize
+    {
+        self.ports.get(port).map_or(0, |v| v.len())
+    }
+
+    pub fn take(&mut self, port: &str) -> DataFrame
+    {
+        self.ports.remove(port)
+            .and_then(|mut v| v.pop())
+            .map(|(_, _, df)| df)
+            .unwrap()
+    }
+
+    pub fn frames(&self, port: &str) -> Vec<&DataFrame>
+    {
+        let mut frames = self.ports.get(port).map_or(vec![], |v| v.iter().collect());
+        frames.sort_by_key(|(order, _, _)| *order);
+        frames.into_iter().map(|(_, _, df)| df).collect()
+    }
+
+    pub fn take_all(&mut self, port: &str) -> Vec<(String, DataFrame)>
+    {
+        let mut frames = self.ports.remove(port).unwrap_or_default();
+        frames.sort_by_key(|(order, _, _)| *order);
+        frames.into_iter()
+            .map(|(_, label, df)| (label, df))
+            .collect()
     }
 }
 
-pub async fn read_csv(ctx: SessionContext, config: &CsvImportConfig) -> Result<Option<DataFrame>>
+pub async fn read_csv(ctx: SessionContext, config: &CsvImportConfig) -> Result<Output>
 {
     let schema = config.fields.as_ref().map(
         |fields| Schema::new(fields.clone())
@@ -52,10 +120,10 @@ pub async fn read_csv(ctx: SessionContext, config: &CsvImportConfig) -> Result<O
         ctx.read_csv(&config.path, options).await?
     };
     
-    Ok(Some(df.limit(0, config.limit)?))
+    Ok(vec![(OUTPUT, df.limit(0, config.limit)?)])
 }
 
-pub async fn read_avro(ctx: SessionContext, config: &AvroImportConfig) -> Result<Option<DataFrame>>
+pub async fn read_avro(ctx: SessionContext, config: &AvroImportConfig) -> Result<Output>
 {
     let options = AvroReadOptions::default();
     let df = if let Some(sql) = &config.sql {
@@ -65,10 +133,10 @@ pub async fn read_avro(ctx: SessionContext, config: &AvroImportConfig) -> Result
         ctx.read_avro(&config.path, options).await?
     };
 
-    Ok(Some(df.limit(0, config.limit)?))
+    Ok(vec![(OUTPUT, df.limit(0, config.limit)?)])
 }
 
-pub async fn read_parquet(ctx: SessionContext, config: &ParquetImportConfig) -> Result<Option<DataFrame>>
+pub async fn read_parquet(ctx: SessionContext, config: &ParquetImportConfig) -> Result<Output>
 {
     let options = ParquetReadOptions::default();
     let df = if let Some(sql) = &config.sql {
@@ -77,15 +145,243 @@ pub async fn read_parquet(ctx: SessionContext, config: &ParquetImportConfig) ->
     } else {
         ctx.read_parquet(&config.path, options).await?
     };
-    Ok(Some(df.limit(0, config.limit)?))
+    Ok(vec![(OUTPUT, df.limit(0, config.limit)?)])
+}
+
+pub async fn query(
+    ctx: SessionContext,
+    data: &mut Data,
+    config: &QueryConfig
+) -> Result<Output>
+{
+    for (name, df) in data.take_all(INPUT) {
+        ctx.register_table(name.as_str(), df.into_view())?;
+    }
+
+    Ok(vec![(OUTPUT, ctx.sql(&config.stmt).await?)])
+}
+
+pub async fn fuzzy_match(
+    ctx: SessionContext,
+    data: &mut Data,
+    config: &FuzzyMatchConfig
+) -> Result<Output>
+{
+    let mut frames = data.take_all(INPUT).into_iter().map(|(_, df)| df);
+    let left = match frames.next() {
+        Some(df) => fuzzy_records(df, config).await?,
+        None => return plan_err!("fuzzy_match: no input"),
+    };
+    let right = match frames.next() {
+        Some(df) => Some(fuzzy_records(df, config).await?),
+        None => None,
+    };
+    if frames.next().is_some() {
+        return plan_err!("fuzzy_match: accepts at most two inputs");
+    }
+
+    let matches = fuzzy::find_matches(config, &left, right.as_deref());
+    let others = right.as_ref().unwrap_or(&left);
+    let left_keys = matches.iter()
+        .map(|m| Some(left[m.left].key.as_str()))
+        .collect::<StringArray>();
+    let right_keys = matches.iter()
+        .map(|m| Some(others[m.right].key.as_str()))
+        .collect::<StringArray>();
+    let scores = matches.iter()
+        .map(|m| m.score)
+        .collect::<Float64Array>();
+
+    let mut columns: Vec<(&str, ArrayRef)> = vec![
+        ("left_key", Arc::new(left_keys)),
+        ("right_key", Arc::new(right_keys)),
+        ("score", Arc::new(scores)),
+    ];
+    if config.cluster {
+        let offset = if right.is_some() { left.len() } else { 0 };
+        let clusters = UInt64Array::from(fuzzy::clusters(&matches, offset));
+        columns.push(("cluster", Arc::new(clusters)));
+    }
+
+    let batch = RecordBatch::try_from_iter(columns)?;
+
+    Ok(vec![(OUTPUT, ctx.read_batch(batch)?)])
+}
+
+pub fn fuzzy_match_outputs(ctx: SessionContext, config: &FuzzyMatchConfig) -> Result<Output>
+{
+    let mut fields = vec![
+        Field::new("left_key", DataType::Utf8, true),
+        Field::new("right_key", DataType::Utf8, true),
+        Field::new("score", DataType::Float64, true),
+    ];
+    if config.cluster {
+        fields.push(Field::new("cluster", DataType::UInt64, true));
+    }
+    let batch = RecordBatch::new_empty(Arc::new(Schema::new(fields)));
+
+    Ok(vec![(OUTPUT, ctx.read_batch(batch)?)])
+}
+
+async fn fuzzy_records(df: DataFrame, config: &FuzzyMatchConfig) -> Result<Vec<fuzzy::Record>>
+{
+    let names = std::iter::once(&config.key)
+        .chain(config.block.iter())
+        .chain(config.fields.iter().map(|f| &f.column))
+        .collect::<Vec<_>>();
+    let exprs = names.iter()
+        .map(|&name| cast(ident(name), DataType::Utf8).alias(name))
+        .collect::<Vec<_>>();
+    let batches = df.select(exprs)?.collect().await?;
+
+    let blocks = config.block.len();
+    let mut records = vec![];
+    for batch in batches {
+        let columns = batch.columns().iter()
+            .map(|c| c.as_string::<i32>())
+            .collect::<Vec<_>>();
+        let value = |c: usize, r: usize| {
+            let col = columns[c];
+            (!col.is_null(r)).then(|| col.value(r).to_string())
+        };
+        for r in 0..batch.num_rows() {
+            let Some(key) = value(0, r) else { continue };
+            records.push(fuzzy::Record {
+                key,
+                block: (1..=blocks).map(|c| value(c, r)).collect(),
+                values: (blocks + 1..columns.len()).map(|c| value(c, r)).collect(),
+            });
+        }
+    }
+
+    Ok(records)
+}
+
+pub async fn profile(
+    ctx: SessionContext,
+    data: &mut Data,
+    config: &ProfileConfig
+) -> Result<Output>
+{
+    let profiles = profile::profile(data.take(INPUT), config).await?;
+    if let Some(path) = &config.report {
+        fs::write(path, profile::to_html(&profiles))?;
+    }
+
+    Ok(vec![(OUTPUT, ctx.read_batch(profile::to_batch(&profiles)?)?)])
+}
+
+pub fn profile_outputs(ctx: SessionContext) -> Result<Output>
+{
+    Ok(vec![(OUTPUT, ctx.read_batch(profile::to_batch(&[])?)?)])
+}
+
+const FAILED_RULES: &str = "failed_rules";
+
+pub async fn validate(data: &mut Data, config: &ValidateConfig) -> Result<Output>
+{
+    let mut df = data.take(INPUT);
+    let mut helpers = vec![];
+    let mut flags = vec![];
+    for (i, rule) in config.rules.iter().enumerate() {
+        let violation = match &rule.check {
+            RuleCheck::Row(expr) => expr.clone(),
+            RuleCheck::Unique(keys) => {
+                let name  = format!("__validate_count_{i}");
+                let count = Expr::WindowFunction(WindowFunction::new(count_udaf(), vec![lit(1)]))
+                    .partition_by(keys.clone())
+                    .build()?;
+                df = df.with_column(&name, count)?;
+                let expr = col(name.as_str()).gt(lit(1));
+                helpers.push(name);
+                expr
+            },
+            RuleCheck::RowCount(min, max) => {
+                let rows = df.clone().count().await?;
+                if min.is_some_and(|n| rows < n) || max.is_some_and(|n| rows > n) {
+                    report(rule, format!("{rows} rows outside {min:?}..{max:?}"))?;
+                }
+                continue
+            },
+            RuleCheck::Schema(fields) => {
+                if let Some(problem) = schema_problem(df.schema(), fields) {
+                    report(rule, problem)?;
+                }
+                continue
+            },
+        };
+        flags.push((rule, violation.is_true()));
+    }
+
+    let mut rejects = vec![];
+    for (rule, flag) in flags {
+        if rule.severity == Severity::reject {
+            rejects.push(when(flag, lit(rule.name.as_str())).end()?);
+        } else {
+            let rows = df.clone().filter(flag)?.count().await?;
+            if rows > 0 {
+                report(rule, format!("{rows} rows in violation"))?;
+            }
+        }
+    }
+
+    let failed = if rejects.is_empty() { lit("") } else { concat_ws(lit(","), rejects) };
+    let df = df.with_column(FAILED_RULES, failed)?;
+    let mut dropped = helpers.iter().map(|h| h.as_str()).collect::<Vec<_>>();
+    let rejected = df.clone()
+        .filter(col(FAILED_RULES).not_eq(lit("")))?
+        .drop_columns(&dropped)?;
+    dropped.push(FAILED_RULES);
+    let valid = df
+        .filter(col(FAILED_RULES).eq(lit("")))?
+        .drop_columns(&dropped)?;
+
+    Ok(vec![(OUTPUT, valid), (REJECTS, rejected)])
+}
+
+pub fn validate_outputs(data: &mut Data) -> Result<Output>
+{
+    let df = data.take(INPUT);
+    let rejected = df.clone().with_column(FAILED_RULES, lit(""))?;
+
+    Ok(vec![(OUTPUT, df), (REJECTS, rejected)])
+}
+
+fn report(rule: &RuleConfig, problem: String) -> Result<()>
+{
+    if rule.severity == Severity::fail {
+        exec_err!("validate: rule '{}' failed, {problem}", rule.name)
+    } else {
+        println!("WARNING [{:?}] {problem}", rule.name);
+        Ok(())
+    }
+}
+
+fn schema_problem(schema: &DFSchema, fields: &[Field]) -> Option<String>
+{
+    let problems = fields.iter()
+        .filter_map(|expected| {
+            let name = expected.name();
+            match schema.field_with_unqualified_name(name) {
+                Err(_) => Some(format!("column '{name}' is missing")),
+                Ok(field) if field.data_type() != expected.data_type() =>
+                    Some(format!("column '{name}' is {} not {}", field.data_type(), expected.data_type())),
+                Ok(field) if field.is_nullable() && !expected.is_nullable() =>
+                    Some(format!("column '{name}' is nullable")),
+                Ok(_) => None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    (!problems.is_empty()).then(|| problems.join(", "))
 }
 
 pub async fn write_csv(
     data: &mut Data,
     config: &CsvExportConfig
-) -> Result<Option<DataFrame>>
+) -> Result<Output>
 {
-    let df = data.left.take().unwrap();
+    let df = data.take(INPUT);
 
     let path = Path::new(&config.path);
     if let Some("csv") = path.extension().and_then(OsStr::to_str) {
@@ -107,15 +403,15 @@ pub async fn write_csv(
         df.write_csv(&config.path, opts, None).await?;
     }
 
-    Ok(None)
+    Ok(vec![])
 }
 
 pub async fn write_json(
     data: &mut Data,
     config: &JsonExportConfig
-) -> Result<Option<DataFrame>>
+) -> Result<Output>
 {
-    let df = data.left.take().unwrap();
+    let df = data.take(INPUT);
 
     let path = Path::new(&config.path);
     if let Some("json") = path.extension().and_then(OsStr::to_str) {
@@ -135,15 +431,20 @@ pub async fn write_json(
         df.write_json(&config.path, opts, None).await?;
     }
 
-    Ok(None)
+    Ok(vec![])
 }
 
 pub async fn write_parquet(
+    ctx: SessionContext,
     data: &mut Data,
     config: &ParquetExportConfig
-) -> Result<Option<DataFrame>>
+) -> Result<Output>
 {
-    let df = data.left.take().unwrap();
+    let df = data.take(INPUT);
+    if let Some(merge) = &config.merge {
+        return merge_parquet(ctx, df, config, merge).await
+    }
+
     let props = Some(
         TableParquetOptions {
             global: ParquetOptions {
@@ -158,76 +459,587 @@ pub async fn write_parquet(
         if config.overwrite {
             let _ = fs::remove_file(path);
         }
-        let props = Some(WriterProperties::builder()
-            .set_compression(config.compress)
-            .build());
-        let file   = fs::File::create(path)?;
         let schema = df.schema().into();
-        let mut writer = ArrowWriter::try_new(file, Arc::new(schema), props)?;
-        let mut recs = 0;
         let batches = df.collect().await?;
-        batches.iter()
-            .for_each(|batch| { recs += batch.num_rows(); writer.write(batch).unwrap(); });
-        writer.close()?;
+        let recs = write_parquet_file(path, schema, &batches, config.compress)?;
         println!("{recs} records written to {:?}", path);
     } else {
         let opts = DataFrameWriteOptions::new();
         df.write_parquet(&config.path, opts, props).await?;
     }
 
-    Ok(None)
+    Ok(vec![])
 }
 
-pub fn difference(data: &mut Data) -> Result<Option<DataFrame>>
+// Rows from the input replace existing rows with the same keys. The merged
+// rows are written to a temporary file which is then renamed over the
+// original so readers never see a partially written file.
+async fn merge_parquet(
+    ctx: SessionContext,
+    df: DataFrame,
+    config: &ParquetExportConfig,
+    merge: &MergeConfig
+) -> Result<Output>
 {
-    let left  = data.left.take().unwrap();
-    let right = data.right.take().unwrap();
+    let path = Path::new(&config.path);
+    if path.extension().and_then(OsStr::to_str) != Some("parquet") {
+        return plan_err!("export: merge requires a .parquet file, not {:?}", path);
+    }
 
-    Ok(Some(left.except(right)?))
+    let mut merged = df.clone();
+    if path.exists() && !merge.delete_unmatched {
+        let fields = df.schema().fields().iter()
+            .map(|field| (field.name().clone(), field.data_type().clone()))
+            .collect::<Vec<_>>();
+        let on = merge.keys.iter()
+            .map(|k| lcol(k).eq(rcol(k)))
+            .collect::<Vec<_>>();
+        let existing = ctx.read_parquet(&config.path, ParquetReadOptions::default()).await?;
+        let kept = alias(existing, LEFT)?
+            .join_on(alias(df, RIGHT)?, JoinType::LeftAnti, on)?
+            .select(fields.iter()
+                .map(|(name, dtype)| cast(lcol(name), dtype.clone()).alias(name))
+                .collect())?;
+        merged = merged.union(kept)?;
+    }
+
+    let schema  = merged.schema().into();
+    let batches = merged.collect().await?;
+    let tmp = path.with_extension("parquet.tmp");
+    let recs = match write_parquet_file(&tmp, schema, &batches, config.compress) {
+        Ok(recs) => recs,
+        Err(e) => { let _ = fs::remove_file(&tmp); return Err(e) }
+    };
+    fs::rename(&tmp, path)?;
+    println!("{recs} records merged into {:?}", path);
+
+    Ok(vec![])
 }
 
-pub fn distinct(data: &mut Data) -> Result<Option<DataFrame>>
+fn write_parquet_file(
+    path: &Path,
+    schema: Schema,
+    batches: &[RecordBatch],
+    compress: Compression
+) -> Result<usize>
 {
-    let df = data.left.take().unwrap();
+    let props = Some(WriterProperties::builder()
+        .set_compression(compress)
+        .build());
+    let file = fs::File::create(path)?;
+    let mut writer = ArrowWriter::try_new(file, Arc::new(schema), props)?;
+    let mut recs = 0;
+    for batch in batches {
+        recs += batch.num_rows();
+        writer.write(batch)?;
+    }
+    writer.close()?;
 
-    Ok(Some(df.distinct()?))
+    Ok(recs)
 }
 
-pub fn intersect(data: &mut Data) -> Result<Option<DataFrame>>
+pub fn columns(data: &mut Data, config: &ColumnsConfig) -> Result<Output>
 {
-    let left  = data.left.take().unwrap();
-    let right = data.right.take().unwrap();
+    let df = data.take(INPUT);
+    let schema = df.schema();
+
+    let names = schema.fields().iter()
+        .map(|field| field.name().as_str())
+        .collect::<Vec<_>>();
+    if let Some(name) = config.named().find(|c| !names.contains(c)) {
+        return plan_err!("columns: column '{name}' not found");
+    }
+
+    let mut cols = schema.iter()
+        .filter(|(_, field)| match &config.select {
+            Some(select) => select.iter().any(|m| m.matches(field)),
+            None => true,
+        })
+        .filter(|(_, field)| !config.deselect.iter().any(|m| m.matches(field)))
+        .map(|(qualifier, field)| {
+            let name = config.output_name(field.name());
+            (Column::from((qualifier, field.as_ref())), name)
+        })
+        .collect::<Vec<_>>();
+
+    if !config.order.is_empty() {
+        let mut ordered = Vec::with_capacity(cols.len());
+        let mut rest_at = None;
+        for item in &config.order {
+            match item {
+                ColumnOrder::Col(name) => {
+                    let ix = cols.iter().position(|(_, c)| c == name)
+                        .ok_or_else(|| plan_datafusion_err!("columns: column '{name}' not found"))?;
+                    ordered.push(cols.remove(ix));
+                },
+                ColumnOrder::Rest => if rest_at.replace(ordered.len()).is_some() {
+                    return plan_err!("columns: only one rest placeholder is allowed");
+                },
+            }
+        }
+        let at = rest_at.unwrap_or(ordered.len());
+        ordered.splice(at..at, cols);
+        cols = ordered;
+    }
 
-    Ok(Some(left.intersect(right)?))
+    let exprs = cols.into_iter()
+        .map(|(column, name)| if column.name == name {
+                Expr::Column(column)
+            } else {
+                Expr::Column(column).alias(name)
+            }
+        )
+        .collect::<Vec<_>>();
+
+    Ok(vec![(OUTPUT, df.select(exprs)?)])
 }
 
-pub fn filter(data: &mut Data, config: &FilterConfig) -> Result<Option<DataFrame>>
+pub fn dedupe(data: &mut Data, config: &DedupeConfig) -> Result<Output>
 {
-    let df = data.left.take().unwrap();
+    const RANK: &str = "__dedupe_rank";
+
+    let df = data.take(INPUT);
+    let order = config.order.iter()
+        .map(|se| match config.keep {
+            Keep::first => se.clone(),
+            Keep::last  => SortExpr::new(se.expr.clone(), !se.asc, !se.nulls_first),
+        })
+        .collect();
+    let rank = row_number()
+        .partition_by(config.keys.clone())
+        .order_by(order)
+        .build()?;
+
+    let ranked = df.with_column(RANK, rank)?;
+    let kept = ranked.clone()
+        .filter(col(RANK).eq(lit(1u64)))?
+        .drop_columns(&[RANK])?;
+    let duplicates = ranked
+        .filter(col(RANK).gt(lit(1u64)))?
+        .drop_columns(&[RANK])?;
 
-    Ok(Some(df.filter(config.expr.clone())?))
+    Ok(vec![(OUTPUT, kept), (DUPLICATES, duplicates)])
 }
 
-pub fn join(data: &mut Data, config: &JoinConfig) -> Result<Option<DataFrame>>
+pub fn diff(data: &mut Data, config: &DiffConfig) -> Result<Output>
 {
-    let left  = data.left.take().unwrap();
-    let right = data.right.take().unwrap();
-    let exprs = config.left_cols.iter().zip(config.right_cols.iter())
-        .map(|(c1, c2)| col(c1).eq(col(format!(r#""{c2}""#))));
+    let left  = alias(data.take(LEFT), LEFT)?;
+    let right = alias(data.take(RIGHT), RIGHT)?;
+    let on = config.keys.iter()
+        .map(|k| lcol(k).eq(rcol(k)))
+        .collect::<Vec<_>>();
+
+    let columns = match &config.columns {
+        Some(columns) => columns.clone(),
+        None => left.schema().fields().iter()
+            .map(|field| field.name())
+            .filter(|&name| !config.keys.contains(name))
+            .filter(|&name| right.schema().has_column_with_unqualified_name(name))
+            .cloned()
+            .collect(),
+    };
+    let flags = columns.iter()
+        .map(|c| binary_expr(lcol(c), Operator::IsDistinctFrom, rcol(c)))
+        .collect::<Vec<_>>();
+    let modified = flags.iter().cloned().reduce(or).unwrap_or(lit(false));
+    let names = columns.iter().zip(flags)
+        .map(|(c, flag)| when(flag, lit(c.as_str())).end())
+        .collect::<Result<Vec<_>>>()?;
+    let names = if names.is_empty() { lit("") } else { concat_ws(lit(","), names) };
 
-    Ok(Some(left.join_on(right, config.join_type, exprs)?))
+    let added   = left.clone().join_on(right.clone(), JoinType::RightAnti, on.clone())?;
+    let removed = left.clone().join_on(right.clone(), JoinType::LeftAnti, on.clone())?;
+    let matched = left.join_on(right, JoinType::Inner, on)?;
+    let unchanged = matched.clone().filter(modified.clone().is_false())?;
+
+    let exprs = config.keys.iter()
+        .map(|k| lcol(k).alias(k))
+        .chain(std::iter::once(names.alias("changed_columns")))
+        .chain(columns.iter().flat_map(|c| [
+            lcol(c).alias(format!("{c}_before")),
+            rcol(c).alias(format!("{c}_after")),
+        ]))
+        .collect::<Vec<_>>();
+    let changed = matched.filter(modified)?.select(exprs)?;
+
+    let tally = |df: &DataFrame, change: &str| df.clone()
+        .aggregate(vec![], vec![count(lit(1)).alias("count")])?
+        .select(vec![lit(change).alias("change"), col("count")]);
+    let summary = tally(&added, ADDED)?
+        .union(tally(&removed, REMOVED)?)?
+        .union(tally(&changed, CHANGED)?)?
+        .union(tally(&unchanged, "unchanged")?)?;
+
+    Ok(vec![
+        (ADDED, added),
+        (REMOVED, removed),
+        (CHANGED, changed),
+        (SUMMARY, summary),
+    ])
 }
 
-pub fn project(data: &mut Data, config: &MapConfig) -> Result<Option<DataFrame>>
+pub fn difference(data: &mut Data) -> Result<Output>
 {
-    let df = data.left.take().unwrap();
+    let left  = data.take(LEFT);
+    let right = data.take(RIGHT);
 
-    Ok(Some(df.select(config.exprs.clone())?))
+    Ok(vec![(OUTPUT, left.except(right)?)])
 }
 
-pub fn select(data: &mut Data, config: &SelectConfig) -> Result<Option<DataFrame>>
+pub fn distinct(data: &mut Data) -> Result<Output>
 {
-    let df = data.left.take().unwrap();
+    let df = data.take(INPUT);
+
+    Ok(vec![(OUTPUT, df.distinct()?)])
+}
+
+pub fn intersect(data: &mut Data) -> Result<Output>
+{
+    let left  = data.take(LEFT);
+    let right = data.take(RIGHT);
+
+    Ok(vec![(OUTPUT, left.intersect(right)?)])
+}
+
+pub fn filter(data: &mut Data, config: &FilterConfig) -> Result<Output>
+{
+    let df = data.take(INPUT);
+    let accepted = df.clone().filter(config.expr.clone())?;
+    let rejected = df.filter(config.expr.clone().is_not_true())?;
+
+    Ok(vec![(TRUE, accepted), (FALSE, rejected)])
+}
+
+pub fn formula(data: &mut Data, config: &FormulaConfig) -> Result<Output>
+{
+    let df = data.take(INPUT);
+    let df = config.exprs.iter()
+        .try_fold(df, |df, (name, expr)| df.with_column(name, expr.clone()))?;
+
+    Ok(vec![(OUTPUT, df)])
+}
+
+pub fn join(data: &mut Data, config: &JoinConfig) -> Result<Output>
+{
+    let left  = alias(data.take(LEFT), LEFT)?;
+    let right = alias(data.take(RIGHT), RIGHT)?;
+    let op = if config.null_equal { Operator::IsNotDistinctFrom } else { Operator::Eq };
+    let mut exprs = config.left_cols.iter().zip(config.right_cols.iter())
+        .map(|(c1, c2)| binary_expr(lcol(c1), op, rcol(c2)))
+        .chain(config.on.clone())
+        .collect::<Vec<_>>();
+
+    match &config.kind {
+        JoinKind::Equi => {},
+        JoinKind::AsOf(asof) => {
+            let (l, r) = (lcol(&asof.left), rcol(&asof.right));
+            match asof.direction {
+                AsOfDirection::backward => exprs.push(r.lt_eq(l)),
+                AsOfDirection::forward  => exprs.push(r.gt_eq(l)),
+                AsOfDirection::nearest  => {},
+            }
+            if let Some(tolerance) = &asof.tolerance {
+                let tolerance = asof_tolerance(&left, asof, tolerance)?;
+                exprs.push(asof_distance(&left, asof)?.lt_eq(tolerance));
+            }
+        },
+        JoinKind::Range(range) => {
+            let (point, start, end) = range_bounds(&left, &right, range)?;
+            exprs.push(start.lt_eq(point.clone()));
+            exprs.push(if range.inclusive { point.lt_eq(end) } else { point.lt(end) });
+        },
+    }
+
+    let (left_unmatched, right_unmatched) = if exprs.is_empty() {
+        if !config.cross {
+            return plan_err!("join: no join conditions given, set cross to join all rows");
+        }
+        (left.clone().limit(0, Some(0))?, right.clone().limit(0, Some(0))?)
+    } else {
+        (
+            left.clone().join_on(right.clone(), JoinType::LeftAnti, exprs.clone())?,
+            left.clone().join_on(right.clone(), JoinType::RightAnti, exprs.clone())?,
+        )
+    };
+
+    let joined = if let JoinKind::AsOf(asof) = &config.kind {
+        let rank = row_number()
+            .partition_by(vec![col(ASOF_ROW)])
+            .order_by(vec![asof_distance(&left, asof)?.sort(true, true)])
+            .build()?;
+        left.with_column(ASOF_ROW, row_number())?
+            .join_on(right, config.join_type, exprs)?
+            .with_column(ASOF_RANK, rank)?
+            .filter(col(ASOF_RANK).eq(lit(1u64)))?
+            .drop_columns(&[ASOF_ROW, ASOF_RANK])?
+    } else {
+        left.join_on(right, config.join_type, exprs)?
+    };
+
+    let mut counts = HashMap::new();
+    joined.schema().fields().iter()
+        .for_each(|field| *counts.entry(field.name().clone()).or_insert(0) += 1);
+    let collides = |name: &String| counts.get(name).is_some_and(|&n| n > 1);
+
+    let renames = joined.schema().iter()
+        .map(|(qualifier, field)| {
+            let column = Column::from((qualifier, field.as_ref()));
+            let name = field.name();
+            let side = qualifier.map(|q| q.table());
+            let renamed = match side {
+                Some(LEFT) if collides(name) =>
+                    format!("{}{name}{}", config.prefixes.0, config.suffixes.0),
+                Some(RIGHT) if collides(name) =>
+                    format!("{}{name}{}", config.prefixes.1, config.suffixes.1),
+                _ => name.clone(),
+            };
+            if &renamed == name {
+                Expr::Column(column)
+            } else {
+                Expr::Column(column).alias(renamed)
+            }
+        })
+        .collect::<Vec<_>>();
+    let joined = joined.select(renames)?;
+
+    Ok(vec![
+        (JOINED, joined),
+        (LEFT_UNMATCHED, left_unmatched),
+        (RIGHT_UNMATCHED, right_unmatched),
+    ])
+}
+
+const ASOF_ROW: &str  = "__asof_row";
+const ASOF_RANK: &str = "__asof_rank";
+const NANOS_PER_DAY: i64 = 86_400_000_000_000;
+
+// Qualifies the columns of a frame with the given name, as the two sides
+// of a join are referred to by their port.
+pub fn alias(df: DataFrame, name: &str) -> Result<DataFrame>
+{
+    let (state, plan) = df.into_parts();
+    let plan = LogicalPlanBuilder::from(plan).alias(name)?.build()?;
+
+    Ok(DataFrame::new(state, plan))
+}
+
+fn lcol(name: &str) -> Expr
+{
+    col(Column::new(Some(LEFT), name))
+}
+
+fn rcol(name: &str) -> Expr
+{
+    col(Column::new(Some(RIGHT), name))
+}
+
+fn asof_distance(left: &DataFrame, config: &AsOfConfig) -> Result<Expr>
+{
+    let (l, r) = (lcol(&config.left), rcol(&config.right));
+    let temporal = left.schema()
+        .field_with_unqualified_name(&config.left)?
+        .data_type()
+        .is_temporal();
+    let distance = if temporal {
+        cast(l, DataType::Int64) - cast(r, DataType::Int64)
+    } else {
+        l - r
+    };
+
+    Ok(abs(distance))
+}
+
+// Takes a tolerance as a number for numeric columns, or as a duration such as
+// '1 day' in the storage unit of temporal ones, to compare with the distance.
+fn asof_tolerance(left: &DataFrame, config: &AsOfConfig, tolerance: &str) -> Result<Expr>
+{
+    let dtype = left.schema().field_with_unqualified_name(&config.left)?.data_type();
+    if !dtype.is_temporal() {
+        return match tolerance.parse::<f64>() {
+            Ok(value) => Ok(lit(value)),
+            Err(_) =>
+                plan_err!("asof join: tolerance '{tolerance}' for {dtype} columns must be a number"),
+        }
+    }
+
+    if tolerance.parse::<f64>().is_ok() {
+        return plan_err!(
+            "asof join: tolerance '{tolerance}' for {dtype} columns must be a duration such as '1 day'"
+        )
+    }
+    let interval = parse_interval_month_day_nano(tolerance)
+        .map_err(|e| plan_datafusion_err!("asof join: invalid tolerance '{tolerance}': {e}"))?;
+    if interval.months != 0 {
+        return plan_err!("asof join: tolerance '{tolerance}' can't be given in months or years")
+    }
+    let nanos = interval.days as i64 * NANOS_PER_DAY + interval.nanoseconds;
+    let per_unit = match dtype {
+        DataType::Timestamp(unit, _) | DataType::Time32(unit) | DataType::Time64(unit)
+            | DataType::Duration(unit) => match unit {
+                TimeUnit::Second      => 1_000_000_000,
+                TimeUnit::Millisecond => 1_000_000,
+                TimeUnit::Microsecond => 1_000,
+                TimeUnit::Nanosecond  => 1,
+            },
+        DataType::Date32 => NANOS_PER_DAY,
+        DataType::Date64 => 1_000_000,
+        _ => return plan_err!("asof join: can't apply a tolerance to {dtype} columns"),
+    };
+
+    Ok(lit(nanos / per_unit))
+}
+
+// Casts the column and the bounds of a range join to a common numeric or
+// temporal type, as other types would be compared as text.
+fn range_bounds(left: &DataFrame, right: &DataFrame, config: &RangeConfig) -> Result<(Expr, Expr, Expr)>
+{
+    let point = left.schema().field_with_unqualified_name(&config.col)?.data_type();
+    let start = right.schema().field_with_unqualified_name(&config.start)?.data_type();
+    let end   = right.schema().field_with_unqualified_name(&config.end)?.data_type();
+    for (name, dtype) in [(&config.col, point), (&config.start, start), (&config.end, end)] {
+        if !dtype.is_numeric() && !dtype.is_temporal() {
+            return plan_err!("range join: {name} is {dtype}, cast it to a numeric or temporal type")
+        }
+    }
+    let Some(dtype) = widen(point, start, false).and_then(|t| widen(&t, end, false)) else {
+        return plan_err!(
+            "range join: can't compare {} ({point}) with {} ({start}) and {} ({end})",
+            config.col, config.start, config.end
+        )
+    };
+
+    Ok((
+        cast(lcol(&config.col), dtype.clone()),
+        cast(rcol(&config.start), dtype.clone()),
+        cast(rcol(&config.end), dtype),
+    ))
+}
+
+pub fn json_parse(data: &mut Data, config: &JsonParseConfig) -> Result<Output>
+{
+    let df = data.take(INPUT);
+    let DataType::Struct(fields) = &config.dtype else {
+        return plan_err!("json_parse: schema must be a struct type")
+    };
+    if df.schema().field_with_unqualified_name(&config.column).is_err() {
+        return plan_err!("json_parse: no column named {}", config.column)
+    }
+
+    let parsed = json::json_parse(config.dtype.clone())?.call(vec![ident(&config.column)]);
+    if !config.flatten {
+        return Ok(vec![(OUTPUT, df.with_column(&config.column, parsed)?)])
+    }
+
+    let exprs = df.schema().fields().iter()
+        .flat_map(|field| if field.name() == &config.column {
+                fields.iter()
+                    .map(|f| get_field(parsed.clone(), f.name().as_str()).alias(f.name()))
+                    .collect()
+            } else {
+                vec![ident(field.name())]
+            }
+        )
+        .collect::<Vec<_>>();
+
+    Ok(vec![(OUTPUT, df.select(exprs)?)])
+}
+
+pub fn project(data: &mut Data, config: &MapConfig) -> Result<Output>
+{
+    let df = data.take(INPUT);
+
+    Ok(vec![(OUTPUT, df.select(config.exprs.clone())?)])
+}
+
+pub fn scd2(data: &mut Data, config: &Scd2Config) -> Result<Output>
+{
+    let current  = alias(data.take(LEFT), LEFT)?;
+    let incoming = alias(data.take(RIGHT), RIGHT)?;
+    let fields = current.schema().fields().iter()
+        .map(|field| (field.name().clone(), field.data_type().clone()))
+        .collect::<Vec<_>>();
+    let missing = [VALID_FROM, VALID_TO, IS_CURRENT].into_iter()
+        .filter(|&name| !fields.iter().any(|(n, _)| n == name))
+        .collect::<Vec<_>>();
+    let effective = config.effective.clone();
+
+    // A dimension without any validity columns is being loaded for the
+    // first time, so every incoming row is opened.
+    if missing.len() == 3 {
+        let dtype = effective.get_type(incoming.schema())?;
+        let exprs = incoming.schema().fields().iter()
+            .map(|field| rcol(field.name()).alias(field.name()))
+            .chain([
+                effective.alias(VALID_FROM),
+                cast(lit(ScalarValue::Null), dtype).alias(VALID_TO),
+                lit(true).alias(IS_CURRENT),
+            ])
+            .collect::<Vec<_>>();
+        return Ok(vec![(OUTPUT, incoming.select(exprs)?)])
+    }
+    if let Some(name) = missing.first() {
+        return plan_err!("scd2: dimension has no {name} column");
+    }
+
+    let keep   = scd2_select(&fields, lcol, &[]);
+    let closed = scd2_select(&fields, lcol, &[
+        (VALID_TO, effective.clone()),
+        (IS_CURRENT, lit(false)),
+    ]);
+    let opened = scd2_select(&fields, rcol, &[
+        (VALID_FROM, effective),
+        (VALID_TO, lit(ScalarValue::Null)),
+        (IS_CURRENT, lit(true)),
+    ]);
+
+    let on = config.keys.iter()
+        .map(|k| lcol(k).eq(rcol(k)))
+        .collect::<Vec<_>>();
+    let modified = config.tracked.iter()
+        .map(|c| binary_expr(lcol(c), Operator::IsDistinctFrom, rcol(c)))
+        .reduce(or)
+        .unwrap_or(lit(false));
+
+    let history = current.clone().filter(lcol(IS_CURRENT).is_not_true())?;
+    let active  = current.filter(lcol(IS_CURRENT).is_true())?;
+    let matched = active.clone().join_on(incoming.clone(), JoinType::Inner, on.clone())?;
+    let missing = active.clone().join_on(incoming.clone(), JoinType::LeftAnti, on.clone())?;
+    let added   = active.join_on(incoming, JoinType::RightAnti, on)?;
+    let changed = matched.clone().filter(modified.clone())?;
+
+    let dimension = history.select(keep.clone())?
+        .union(matched.filter(modified.is_false())?.select(keep.clone())?)?
+        .union(changed.clone().select(closed.clone())?)?
+        .union(changed.select(opened.clone())?)?
+        .union(added.select(opened)?)?
+        .union(missing.select(if config.close_missing { closed } else { keep })?)?;
+
+    Ok(vec![(OUTPUT, dimension)])
+}
+
+pub const VALID_FROM: &str = "valid_from";
+pub const VALID_TO: &str   = "valid_to";
+pub const IS_CURRENT: &str = "is_current";
+
+fn scd2_select(
+    fields: &[(String, DataType)],
+    source: fn(&str) -> Expr,
+    overrides: &[(&str, Expr)]
+) -> Vec<Expr>
+{
+    fields.iter()
+        .map(|(name, dtype)| {
+            let expr = overrides.iter()
+                .find(|(n, _)| n == name)
+                .map_or_else(|| source(name), |(_, expr)| expr.clone());
+            cast(expr, dtype.clone()).alias(name)
+        })
+        .collect()
+}
+
+pub fn select(data: &mut Data, config: &SelectConfig) -> Result<Output>
+{
+    let df = data.take(INPUT);
     let exprs = config.columns.iter()
         .map(|c| if let Some(alias) = config.aliases.get(c) {
                 col(c).alias(alias)
@@ -237,32 +1049,100 @@ pub fn select(data: &mut Data, config: &SelectConfig) -> Result<Option<DataFrame
         )
         .collect::<Vec<_>>();
 
-    Ok(Some(df.select(exprs)?))
+    Ok(vec![(OUTPUT, df.select(exprs)?)])
 }
 
-pub fn sort(data: &mut Data, config: &SortConfig) -> Result<Option<DataFrame>>
+pub fn sort(data: &mut Data, config: &SortConfig) -> Result<Output>
 {
-    let df = data.left.take().unwrap();
+    let df = data.take(INPUT);
 
-    Ok(Some(df.sort(config.exprs.clone())?))
+    Ok(vec![(OUTPUT, df.sort(config.exprs.clone())?)])
 }
 
-pub fn summarize(data: &mut Data, config: &SummarizeConfig) -> Result<Option<DataFrame>>
+pub fn summarize(data: &mut Data, config: &SummarizeConfig) -> Result<Output>
 {
-    let df = data.left.take().unwrap();
+    let df = data.take(INPUT);
 
-    Ok(Some(df.aggregate(config.group.clone(), config.aggr.clone())?))
+    Ok(vec![(OUTPUT, df.aggregate(config.group.clone(), config.aggr.clone())?)])
 }
 
-pub fn union(data: &mut Data, config: &UnionConfig) -> Result<Option<DataFrame>>
+pub fn union(data: &mut Data, config: &UnionConfig) -> Result<Output>
 {
-    let left = data.left.take().unwrap();
-    let right = data.right.take().unwrap();
-    let df = if config.distinct {
-        left.union_distinct(right)?
+    let frames = data.take_all(INPUT);
+    let frames = if config.by_name {
+        align_by_name(frames, config.allow_utf8)?
     } else {
-        left.union(right)?
+        frames
     };
 
-    Ok(Some(df))
+    let mut frames = frames.into_iter()
+        .map(|(label, df)| match &config.source {
+            Some(name) => df.with_column(name, lit(label)),
+            None => Ok(df),
+        });
+    let first = frames.next().unwrap()?;
+    let df = frames.try_fold(first, |acc, df| acc.union(df?))?;
+    let df = if config.distinct { df.distinct()? } else { df };
+
+    Ok(vec![(OUTPUT, df)])
 }
+
+pub fn unnest(data: &mut Data, config: &UnnestConfig) -> Result<Output>
+{
+    let df = data.take(INPUT);
+    let columns = config.columns.iter().map(|c| c.as_str()).collect::<Vec<_>>();
+    let options = UnnestOptions::new().with_preserve_nulls(config.preserve_nulls);
+
+    Ok(vec![(OUTPUT, df.unnest_columns_with_options(&columns, options)?)])
+}
+
+fn align_by_name(
+    frames: Vec<(String, DataFrame)>,
+    allow_utf8: bool
+) -> Result<Vec<(String, DataFrame)>>
+{
+    let mut fields: Vec<(String, DataType)> = vec![];
+    for (_, df) in &frames {
+        for field in df.schema().fields() {
+            match fields.iter_mut().find(|(name, _)| name == field.name()) {
+                Some((name, dtype)) => {
+                    *dtype = widen(dtype, field.data_type(), allow_utf8)
+                        .ok_or_else(|| plan_datafusion_err!(
+                            "union: column '{name}' has incompatible types {dtype} and {}",
+                            field.data_type()
+                        ))?;
+                },
+                None => fields.push((field.name().clone(), field.data_type().clone())),
+            }
+        }
+    }
+
+    frames.into_iter()
+        .map(|(label, df)| {
+            let exprs = fields.iter()
+                .map(|(name, dtype)| {
+                    let expr = match df.schema().field_with_unqualified_name(name) {
+                        Ok(field) if field.data_type() == dtype => ident(name),
+                        Ok(_) => cast(ident(name), dtype.clone()),
+                        Err(_) => lit(ScalarValue::try_from(dtype)?),
+                    };
+                    Ok(expr.alias(name))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok((label, df.select(exprs)?))
+        })
+        .collect()
+}
+
+fn widen(a: &DataType, b: &DataType, allow_utf8: bool) -> Option<DataType>
+{
+    match (a, b) {
+        _ if a == b => Some(a.clone()),
+        (DataType::Null, t) | (t, DataType::Null) => Some(t.clone()),
+        _ if a.is_numeric() && b.is_numeric() => comparison_coercion(a, b),
+        _ if a.is_temporal() && b.is_temporal() => comparison_coercion(a, b),
+        _ if allow_utf8 => Some(DataType::Utf8),
+        _ => None,
+    }
+}
+
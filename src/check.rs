@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use datafusion::arrow::datatypes::{DataType, Field};
+use datafusion::common::{Column, DFSchema};
+use datafusion::logical_expr::ExprSchemable;
+
+use crate::actions::{alias, INPUT, IS_CURRENT, LEFT, RIGHT, VALID_FROM, VALID_TO};
+use crate::expr::{convert, Expression};
+use crate::macros::Macros;
+use crate::plans::{self, Check};
+use crate::tool::ToolData;
+
+#[derive(Debug)]
+pub struct CheckError {
+    pub tool: String,
+    pub path: String,
+    pub message: String,
+}
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "[{}] {}: {}", self.tool, self.path, self.message)
+    }
+}
+
+// Where an expression is evaluated: per row in the named tool, as a
+// summarize aggregate, or inside the named aggregate.
+#[derive(Clone, Copy)]
+enum Scope<'a> {
+    Row(&'a str),
+    Aggregate,
+    Nested(&'a str),
+}
+
+struct Checker<'a> {
+    tool: &'a str,
+    macros: &'a Macros,
+    errors: Vec<CheckError>,
+}
+
+// Checks the columns and expressions of a tool against the schemas of its
+// inputs. Paths below a text expression follow its parsed form.
+pub fn tool(
+    index: usize,
+    tool: &plans::Tool,
+    data: &ToolData,
+    macros: &Macros
+) -> Vec<CheckError>
+{
+    use plans::Tool::*;
+
+    let mut checker = Checker { tool: tool.id(), macros, errors: vec![] };
+    let c = &mut checker;
+    let path = format!("$.tools[{index}]");
+    let schema = |port: &str| data.frames(port).first().map(|df| df.schema().clone());
+    let input = schema(INPUT).unwrap_or_else(DFSchema::empty);
+    let left  = schema(LEFT).unwrap_or_else(DFSchema::empty);
+    let right = schema(RIGHT).unwrap_or_else(DFSchema::empty);
+
+    match tool {
+        filter(t) => c.predicate(&input, &t.expr, &format!("{path}.expr"), "filter"),
+        formula(t) => {
+            let mut schema = input;
+            for (i, field) in t.exprs.iter().enumerate() {
+                let path = format!("{path}.exprs[{i}].expr");
+                let dtype = c.expression(&schema, &field.expr, &path, Scope::Row("formula"));
+                let Some(dtype) = dtype else { continue };
+                let added = vec![Field::new(field.name, dtype, true)];
+                if let Ok(added) = DFSchema::from_unqualified_fields(added.into(), HashMap::new()) {
+                    schema.merge(&added);
+                }
+            }
+        },
+        map(t) => t.exprs.iter().enumerate().for_each(|(i, expr)| {
+            c.expression(&input, expr, &format!("{path}.exprs[{i}]"), Scope::Row("map"));
+        }),
+        sort(t) => t.exprs.iter().enumerate().for_each(|(i, se)| {
+            let path = format!("{path}.exprs[{i}].expr");
+            c.expression(&input, &se.expr, &path, Scope::Row("sort"));
+        }),
+        summarize(t) => {
+            t.group.iter().enumerate().for_each(|(i, expr)| {
+                c.expression(&input, expr, &format!("{path}.group[{i}]"), Scope::Row("group"));
+            });
+            t.aggr.iter().enumerate().for_each(|(i, expr)| {
+                c.aggregate(&input, expr, &format!("{path}.aggr[{i}]"));
+            });
+        },
+        dedupe(t) => {
+            c.columns(&input, &t.keys, &format!("{path}.keys"));
+            t.order.iter().enumerate().for_each(|(i, se)| {
+                let path = format!("{path}.order[{i}].expr");
+                c.expression(&input, &se.expr, &path, Scope::Row("dedupe"));
+            });
+        },
+        diff(t) => {
+            for schema in [&left, &right] {
+                c.columns(schema, &t.keys, &format!("{path}.keys"));
+                if let Some(cols) = &t.columns {
+                    c.columns(schema, cols, &format!("{path}.columns"));
+                }
+            }
+        },
+        join(t) => {
+            c.columns(&left, &t.lt, &format!("{path}.lt"));
+            c.columns(&right, &t.rt, &format!("{path}.rt"));
+            if let Some(asof) = &t.asof {
+                c.column(&left, asof.left, &format!("{path}.asof.left"));
+                c.column(&right, asof.right, &format!("{path}.asof.right"));
+            }
+            if let Some(range) = &t.range {
+                c.column(&left, range.col, &format!("{path}.range.col"));
+                c.column(&right, range.start, &format!("{path}.range.start"));
+                c.column(&right, range.end, &format!("{path}.range.end"));
+            }
+            if let Some(on) = &t.on {
+                c.predicate(&joined(data), on, &format!("{path}.on"), "join");
+            }
+        },
+        scd2(t) => {
+            // A dimension without validity columns is an initial load, which
+            // only takes the tracked columns from the incoming rows.
+            let initial = [VALID_FROM, VALID_TO, IS_CURRENT].iter()
+                .all(|name| left.field_with_unqualified_name(name).is_err());
+            for schema in [&left, &right] {
+                c.columns(schema, &t.keys, &format!("{path}.keys"));
+                if !initial || schema == &right {
+                    c.columns(schema, &t.tracked, &format!("{path}.tracked"));
+                }
+            }
+            if let Some(effective) = &t.effective {
+                let path = format!("{path}.effective");
+                c.expression(&joined(data), effective, &path, Scope::Row("scd2"));
+            }
+        },
+        select(t) => c.columns(&input, &t.columns, &format!("{path}.columns")),
+        unnest(t) => c.columns(&input, &t.columns, &format!("{path}.columns")),
+        json_parse(t) => {
+            let path = format!("{path}.column");
+            match c.column(&input, t.column, &path) {
+                Some(DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View) | None => {},
+                Some(dtype) => {
+                    c.error(&path, format!("expected a string column, found {dtype}"));
+                },
+            }
+        },
+        fuzzy_match(t) => {
+            for df in data.frames(INPUT) {
+                let schema = df.schema();
+                c.column(schema, t.key, &format!("{path}.key"));
+                if let Some(block) = &t.block {
+                    c.columns(schema, block, &format!("{path}.block"));
+                }
+                t.fields.iter().enumerate().for_each(|(i, field)| {
+                    c.column(schema, field.column, &format!("{path}.fields[{i}].column"));
+                });
+            }
+        },
+        validate(t) => t.rules.iter().enumerate().for_each(|(i, rule)| {
+            let path = format!("{path}.rules[{i}].check");
+            let row = Scope::Row("validate");
+            match &rule.check {
+                Check::not_null(column) => {
+                    c.column(&input, column, &format!("{path}.not_null"));
+                },
+                Check::unique(cols) => c.columns(&input, cols, &format!("{path}.unique")),
+                Check::regex { column, .. } => {
+                    c.column(&input, column, &format!("{path}.regex.column"));
+                },
+                Check::range { column, min, max } => {
+                    c.column(&input, column, &format!("{path}.range.column"));
+                    for (name, bound) in [("min", min), ("max", max)] {
+                        if let Some(bound) = bound {
+                            c.expression(&input, bound, &format!("{path}.range.{name}"), row);
+                        }
+                    }
+                },
+                Check::allowed { column, values } => {
+                    c.column(&input, column, &format!("{path}.allowed.column"));
+                    values.iter().enumerate().for_each(|(j, value)| {
+                        c.expression(&input, value, &format!("{path}.allowed.values[{j}]"), row);
+                    });
+                },
+                Check::expr(expr) => c.predicate(&input, expr, &format!("{path}.expr"), "validate"),
+                Check::row_count { .. } | Check::schema(_) => {},
+            }
+        }),
+        _ => {},
+    }
+
+    checker.errors
+}
+
+// Checks that every expression of a tool can be built, before any schema
+// is known, as the tool's config is built from them.
+pub fn expressions(index: usize, tool: &plans::Tool, macros: &Macros) -> Vec<CheckError>
+{
+    let mut checker = Checker { tool: tool.id(), macros, errors: vec![] };
+    for (path, expr) in paths(index, tool) {
+        checker.convert(expr, &path);
+    }
+
+    checker.errors
+}
+
+fn paths<'p, 'a>(index: usize, tool: &'p plans::Tool<'a>) -> Vec<(String, &'p Expression<'a>)>
+{
+    use plans::Tool::*;
+
+    let path = format!("$.tools[{index}]");
+    let mut found = vec![];
+    match tool {
+        filter(t) => found.push((format!("{path}.expr"), &t.expr)),
+        formula(t) => found.extend(t.exprs.iter().enumerate()
+            .map(|(i, field)| (format!("{path}.exprs[{i}].expr"), &field.expr))),
+        map(t) => found.extend(t.exprs.iter().enumerate()
+            .map(|(i, expr)| (format!("{path}.exprs[{i}]"), expr))),
+        sort(t) => found.extend(t.exprs.iter().enumerate()
+            .map(|(i, se)| (format!("{path}.exprs[{i}].expr"), &se.expr))),
+        summarize(t) => {
+            found.extend(t.group.iter().enumerate()
+                .map(|(i, expr)| (format!("{path}.group[{i}]"), expr)));
+            found.extend(t.aggr.iter().enumerate()
+                .map(|(i, expr)| (format!("{path}.aggr[{i}]"), expr)));
+        },
+        dedupe(t) => found.extend(t.order.iter().enumerate()
+            .map(|(i, se)| (format!("{path}.order[{i}].expr"), &se.expr))),
+        join(t) => found.extend(t.on.iter().map(|on| (format!("{path}.on"), on))),
+        scd2(t) => found.extend(t.effective.iter().map(|e| (format!("{path}.effective"), e))),
+        validate(t) => t.rules.iter().enumerate().for_each(|(i, rule)| {
+            let path = format!("{path}.rules[{i}].check");
+            match &rule.check {
+                Check::range { min, max, .. } => {
+                    found.extend(min.iter().map(|e| (format!("{path}.range.min"), e)));
+                    found.extend(max.iter().map(|e| (format!("{path}.range.max"), e)));
+                },
+                Check::allowed { values, .. } => found.extend(values.iter().enumerate()
+                    .map(|(j, value)| (format!("{path}.allowed.values[{j}]"), value))),
+                Check::expr(expr) => found.push((format!("{path}.expr"), expr)),
+                _ => {},
+            }
+        }),
+        _ => {},
+    }
+
+    found
+}
+
+// The schema seen by expressions over two inputs, with the columns
+// qualified as left and right.
+fn joined(data: &ToolData) -> DFSchema
+{
+    let side = |port: &str| data.frames(port).first()
+        .and_then(|&df| alias(df.clone(), port).ok())
+        .map(|df| df.schema().clone())
+        .unwrap_or_else(DFSchema::empty);
+
+    side(LEFT).join(&side(RIGHT)).unwrap_or_else(|_| DFSchema::empty())
+}
+
+impl Checker<'_> {
+    fn error(&mut self, path: &str, message: impl Into<String>) -> Option<DataType>
+    {
+        self.errors.push(CheckError {
+            tool: self.tool.into(),
+            path: path.into(),
+            message: message.into(),
+        });
+
+        None
+    }
+
+    fn column(&mut self, schema: &DFSchema, name: &str, path: &str) -> Option<DataType>
+    {
+        match schema.field_with_unqualified_name(name) {
+            Ok(field) => Some(field.data_type().clone()),
+            Err(_) if schema.has_column_with_unqualified_name(name) =>
+                self.error(path, format!("ambiguous column '{name}'")),
+            Err(_) => self.error(path, format!("unknown column '{name}'")),
+        }
+    }
+
+    fn columns(&mut self, schema: &DFSchema, names: &[&str], path: &str)
+    {
+        names.iter().enumerate().for_each(|(i, name)| {
+            self.column(schema, name, &format!("{path}[{i}]"));
+        });
+    }
+
+    fn predicate(&mut self, schema: &DFSchema, expr: &Expression, path: &str, tool: &str)
+    {
+        match self.expression(schema, expr, path, Scope::Row(tool)) {
+            Some(DataType::Boolean | DataType::Null) | None => {},
+            Some(dtype) => {
+                self.error(path, format!("expected a boolean expression, found {dtype}"));
+            },
+        }
+    }
+
+    // Builds the children first so the deepest expression which cannot be
+    // built is reported.
+    fn convert(&mut self, expr: &Expression, path: &str) -> bool
+    {
+        let mut valid = true;
+        for (suffix, child) in expr.children() {
+            valid &= self.convert(child, &format!("{path}{suffix}"));
+        }
+        if !valid {
+            return false
+        }
+
+        if let Err(e) = convert(expr, self.macros) {
+            self.error(path, e.strip_backtrace());
+            return false
+        }
+
+        true
+    }
+
+    fn aggregate(&mut self, schema: &DFSchema, expr: &Expression, path: &str)
+    {
+        let inner = match expr {
+            Expression::alias(inner, _) => inner.as_ref(),
+            expr => expr,
+        };
+        if inner.is_aggregate() || matches!(inner, Expression::filter(..)) {
+            self.expression(schema, expr, path, Scope::Aggregate);
+        } else {
+            self.error(path, format!("summarize expects an aggregate, found '{}'", inner.name()));
+        }
+    }
+
+    // Checks the children first so the deepest bad expression is reported,
+    // then asks DataFusion for the type of the node itself.
+    fn expression(
+        &mut self,
+        schema: &DFSchema,
+        expr: &Expression,
+        path: &str,
+        scope: Scope
+    ) -> Option<DataType>
+    {
+        match expr {
+            Expression::col(name) => return self.column(schema, name, path),
+            Expression::qcol(qualifier, name) => {
+                let column = Column::new(Some(*qualifier), *name);
+                return match schema.field_from_column(&column) {
+                    Ok(field) => Some(field.data_type().clone()),
+                    Err(_) => self.error(path, format!("unknown column '{qualifier}.{name}'")),
+                }
+            },
+            _ => {},
+        }
+
+        let name = expr.name();
+        let scope = if expr.is_aggregate() {
+            match scope {
+                Scope::Row(tool) => {
+                    return self.error(path, format!("aggregate '{name}' cannot be used in {tool}"))
+                },
+                Scope::Nested(outer) => {
+                    let message = format!("aggregate '{name}' cannot be nested in '{outer}'");
+                    return self.error(path, message)
+                },
+                Scope::Aggregate => Scope::Nested(name),
+            }
+        } else {
+            scope
+        };
+        if let Expression::filter(inner, _) = expr {
+            if !inner.is_aggregate() {
+                let message = format!("filter expects an aggregate, found '{}'", inner.name());
+                return self.error(path, message)
+            }
+        }
+
+        let mut valid = true;
+        for (i, (suffix, child)) in expr.children().into_iter().enumerate() {
+            let scope = match expr {
+                Expression::filter(..) if i == 1 => Scope::Nested("filter"),
+                _ => scope,
+            };
+            valid &= self.expression(schema, child, &format!("{path}{suffix}"), scope).is_some();
+        }
+        if !valid {
+            return None
+        }
+
+        match convert(expr, self.macros).and_then(|expr| expr.get_type(schema)) {
+            Ok(dtype) => Some(dtype),
+            Err(e) => self.error(path, e.strip_backtrace()),
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use std::convert::From;
 use datafusion::parquet::basic::Compression;
 
-use crate::plans::{ExportCsv, ExportJson, ExportParquet};
+use crate::plans::{ExportCsv, ExportJson, ExportParquet, MergeParquet};
 
 #[derive(Clone, Debug)]
 pub struct CsvExportConfig {
@@ -38,6 +38,7 @@ pub struct ParquetExportConfig {
     pub path: String,
     pub compress: Compression,
     pub overwrite: bool,
+    pub merge: Option<MergeConfig>,
 }
 impl From<&ExportParquet<'_>> for ParquetExportConfig {
     fn from(config: &ExportParquet) -> ParquetExportConfig
@@ -46,6 +47,22 @@ impl From<&ExportParquet<'_>> for ParquetExportConfig {
             path: config.path.into(),
             compress: config.compress.map_or(Compression::UNCOMPRESSED, |v| v.into()),
             overwrite: config.overwrite.unwrap_or(false),
+            merge: config.merge.as_ref().map(|m| m.into()),
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Debug)]
+pub struct MergeConfig {
+    pub keys: Vec<String>,
+    pub delete_unmatched: bool,
+}
+impl From<&MergeParquet<'_>> for MergeConfig {
+    fn from(config: &MergeParquet) -> MergeConfig
+    {
+        MergeConfig {
+            keys: config.keys.iter().map(|&k| k.into()).collect(),
+            delete_unmatched: config.delete_unmatched.unwrap_or(false),
+        }
+    }
+}
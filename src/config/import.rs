@@ -30,7 +30,7 @@ impl From<&ImportCsv<'_>> for CsvImportConfig {
             .map(|field| 
                 Field::new(
                     field.column,
-                    field.variant.into(),
+                    field.variant.clone().into(),
                     field.nullable.unwrap_or(true)
                 ))
             .collect());
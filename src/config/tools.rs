@@ -1,31 +1,317 @@
 use std::collections::HashMap;
 use std::convert::From;
-use datafusion::prelude::{Expr, JoinType};
-use datafusion::logical_expr::SortExpr;
+use datafusion::arrow::datatypes::{DataType, Field};
+use datafusion::common::{plan_datafusion_err, plan_err, DataFusionError};
+use datafusion::error::Result;
+use datafusion::prelude::{ident, lit, now, or, Expr, JoinType};
+use datafusion::logical_expr::{binary_expr, Operator, SortExpr};
+use regex::Regex;
 
-use crate::plans;
-use crate::expr::convert;
+use crate::plans::{self, AsOfDirection, Keep, NameCase, Severity, Similarity};
+use crate::expr::{convert, Expression};
+use crate::macros::Macros;
+
+#[derive(Clone, Debug)]
+pub enum ColumnMatch {
+    Name(String),
+    Regex(Regex),
+    Type(DataType),
+}
+impl ColumnMatch {
+    pub fn matches(&self, field: &Field) -> bool
+    {
+        match self {
+            ColumnMatch::Name(name)  => field.name() == name,
+            ColumnMatch::Regex(re)   => re.is_match(field.name()),
+            ColumnMatch::Type(dtype) => field.data_type() == dtype,
+        }
+    }
+}
+impl TryFrom<&plans::ColumnMatch<'_>> for ColumnMatch {
+    type Error = DataFusionError;
+
+    fn try_from(config: &plans::ColumnMatch) -> Result<Self>
+    {
+        use plans::ColumnMatch::*;
+
+        Ok(match config {
+            name(v)  => ColumnMatch::Name((*v).into()),
+            regex(v) => ColumnMatch::Regex(compile(v)?),
+            dtype(v) => ColumnMatch::Type(v.clone().into()),
+        })
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex>
+{
+    Regex::new(pattern).map_err(|e| plan_datafusion_err!("invalid regex '{pattern}': {e}"))
+}
+
+#[derive(Clone, Debug)]
+pub enum ColumnOrder {
+    Col(String),
+    Rest,
+}
+
+#[derive(Clone, Debug)]
+pub struct ColumnsConfig {
+    pub select: Option<Vec<ColumnMatch>>,
+    pub deselect: Vec<ColumnMatch>,
+    pub rename: HashMap<String, String>,
+    pub replace: Vec<(Regex, String)>,
+    pub normalize: Vec<NameCase>,
+    pub order: Vec<ColumnOrder>,
+}
+impl ColumnsConfig {
+    pub fn named(&self) -> impl Iterator<Item = &str>
+    {
+        let matched = self.select.iter().flatten()
+            .chain(self.deselect.iter())
+            .filter_map(|m| match m {
+                ColumnMatch::Name(name) => Some(name.as_str()),
+                _ => None
+            });
+
+        matched.chain(self.rename.keys().map(|k| k.as_str()))
+    }
+
+    pub fn output_name(&self, name: &str) -> String
+    {
+        let name = self.rename.get(name).map_or(name, |v| v.as_str());
+        let name = self.replace.iter()
+            .fold(name.to_string(), |s, (re, with)| re.replace_all(&s, with.as_str()).into());
+
+        self.normalize.iter()
+            .fold(name, |s, case| match case {
+                NameCase::trim       => s.trim().into(),
+                NameCase::lowercase  => s.to_lowercase(),
+                NameCase::uppercase  => s.to_uppercase(),
+                NameCase::snake_case => snake_case(&s),
+            })
+    }
+}
+impl TryFrom<&plans::Columns<'_>> for ColumnsConfig {
+    type Error = DataFusionError;
+
+    fn try_from(config: &plans::Columns) -> Result<Self>
+    {
+        let select = config.select.as_ref()
+            .map(|v| v.iter().map(|m| m.try_into()).collect::<Result<_>>())
+            .transpose()?;
+        let deselect = config.deselect.iter().flatten()
+            .map(|m| m.try_into())
+            .collect::<Result<_>>()?;
+        let rename = config.rename.iter().flatten()
+            .map(|(&k, &v)| (k.into(), v.into()))
+            .collect();
+        let replace = config.replace.iter().flatten()
+            .map(|r| Ok((compile(r.pattern)?, r.with.into())))
+            .collect::<Result<_>>()?;
+        let normalize = config.normalize.clone().unwrap_or_default();
+        let order = config.order.iter().flatten()
+            .map(|o| match o {
+                plans::ColumnOrder::col(name) => ColumnOrder::Col((*name).into()),
+                plans::ColumnOrder::rest      => ColumnOrder::Rest,
+            })
+            .collect();
+
+        Ok(Self { select, deselect, rename, replace, normalize, order })
+    }
+}
+
+fn snake_case(name: &str) -> String
+{
+    let mut s = String::with_capacity(name.len());
+    let mut prev: Option<char> = None;
+    for c in name.trim().chars() {
+        if c.is_alphanumeric() {
+            let boundary = prev.is_some_and(|p| p.is_lowercase() || p.is_numeric());
+            if c.is_uppercase() && boundary {
+                s.push('_');
+            }
+            s.extend(c.to_lowercase());
+        } else if !s.is_empty() && !s.ends_with('_') {
+            s.push('_');
+        }
+        prev = Some(c);
+    }
+
+    s.trim_end_matches('_').into()
+}
+
+#[derive(Clone, Debug)]
+pub struct DedupeConfig {
+    pub keys: Vec<Expr>,
+    pub order: Vec<SortExpr>,
+    pub keep: Keep,
+}
+impl TryFrom<(&plans::Dedupe<'_>, &Macros)> for DedupeConfig {
+    type Error = DataFusionError;
+
+    fn try_from((config, macros): (&plans::Dedupe, &Macros)) -> Result<Self>
+    {
+        // Without an order the row kept is arbitrary, so last is the same
+        // as first.
+        let keep = config.keep.unwrap_or_default();
+        if matches!(keep, Keep::last) && config.order.is_empty() {
+            return plan_err!("dedupe keep last requires an order")
+        }
+
+        let keys  = config.keys.iter().map(|&k| ident(k)).collect();
+        let order = config.order.iter().map(|se| sort_expr(se, macros)).collect::<Result<_>>()?;
+
+        Ok(Self { keys, order, keep })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DiffConfig {
+    pub keys: Vec<String>,
+    pub columns: Option<Vec<String>>,
+}
+impl From<&plans::Diff<'_>> for DiffConfig {
+    fn from(config: &plans::Diff) -> Self
+    {
+        Self {
+            keys: config.keys.iter().map(|&k| k.into()).collect(),
+            columns: config.columns.as_ref()
+                .map(|cols| cols.iter().map(|&c| c.into()).collect()),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct FilterConfig {
     pub expr: Expr,
 }
-impl From<&plans::Filter<'_>> for FilterConfig {
-    fn from(config: &plans::Filter) -> Self
+impl TryFrom<(&plans::Filter<'_>, &Macros)> for FilterConfig {
+    type Error = DataFusionError;
+
+    fn try_from((config, macros): (&plans::Filter, &Macros)) -> Result<Self>
     {
-        Self { expr: convert(&config.expr) }
+        Ok(Self { expr: convert(&config.expr, macros)? })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FormulaConfig {
+    pub exprs: Vec<(String, Expr)>,
+}
+impl TryFrom<(&plans::Formula<'_>, &Macros)> for FormulaConfig {
+    type Error = DataFusionError;
+
+    fn try_from((config, macros): (&plans::Formula, &Macros)) -> Result<Self>
+    {
+        let exprs = config.exprs.iter()
+            .map(|field| Ok((field.name.into(), convert(&field.expr, macros)?)))
+            .collect::<Result<_>>()?;
+
+        Ok(Self { exprs })
     }
+}
 
+#[derive(Clone, Debug)]
+pub struct FuzzyField {
+    pub column: String,
+    pub algorithm: Similarity,
+    pub weight: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct FuzzyMatchConfig {
+    pub key: String,
+    pub fields: Vec<FuzzyField>,
+    pub block: Vec<String>,
+    pub threshold: f64,
+    pub ignore_case: bool,
+    pub cluster: bool,
+}
+impl From<&plans::FuzzyMatch<'_>> for FuzzyMatchConfig {
+    fn from(config: &plans::FuzzyMatch) -> Self
+    {
+        let fields = config.fields.iter()
+            .map(|field| FuzzyField {
+                column: field.column.into(),
+                algorithm: field.algorithm,
+                weight: field.weight.unwrap_or(1.0),
+            })
+            .collect();
+        let block = config.block.iter().flatten()
+            .map(|&c| c.into())
+            .collect();
+
+        Self {
+            fields,
+            block,
+            key: config.key.into(),
+            threshold: config.threshold,
+            ignore_case: config.ignore_case.unwrap_or(true),
+            cluster: config.cluster.unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AsOfConfig {
+    pub left: String,
+    pub right: String,
+    pub direction: AsOfDirection,
+    pub tolerance: Option<String>,
+}
+impl From<&plans::AsOfJoin<'_>> for AsOfConfig {
+    fn from(config: &plans::AsOfJoin) -> Self
+    {
+        Self {
+            left: config.left.into(),
+            right: config.right.into(),
+            direction: config.direction.unwrap_or_default(),
+            tolerance: config.tolerance.map(|t| t.into()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RangeConfig {
+    pub col: String,
+    pub start: String,
+    pub end: String,
+    pub inclusive: bool,
+}
+impl From<&plans::RangeJoin<'_>> for RangeConfig {
+    fn from(config: &plans::RangeJoin) -> Self
+    {
+        Self {
+            col: config.col.into(),
+            start: config.start.into(),
+            end: config.end.into(),
+            inclusive: config.inclusive.unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum JoinKind {
+    Equi,
+    AsOf(AsOfConfig),
+    Range(RangeConfig),
 }
 
 #[derive(Clone, Debug)]
 pub struct JoinConfig {
+    pub kind: JoinKind,
     pub join_type: JoinType,
     pub left_cols: Vec<String>,
     pub right_cols: Vec<String>,
+    pub on: Option<Expr>,
+    pub cross: bool,
+    pub null_equal: bool,
+    pub prefixes: (String, String),
+    pub suffixes: (String, String),
 }
-impl From<&plans::Join<'_>> for JoinConfig {
-    fn from(config: &plans::Join) -> Self
+impl TryFrom<(&plans::Join<'_>, &Macros)> for JoinConfig {
+    type Error = DataFusionError;
+
+    fn try_from((config, macros): (&plans::Join, &Macros)) -> Result<Self>
     {
         use plans::JoinType::*;
 
@@ -38,6 +324,20 @@ impl From<&plans::Join<'_>> for JoinConfig {
             right_semi => JoinType::RightSemi,
             left_anti  => JoinType::LeftAnti,
             right_anti => JoinType::RightAnti,
+            asof       => JoinType::Left,
+            range      => JoinType::Inner,
+        };
+
+        let kind = match config.variant {
+            asof => match &config.asof {
+                Some(section) => JoinKind::AsOf(section.into()),
+                None => return plan_err!("asof join requires an asof section"),
+            },
+            range => match &config.range {
+                Some(section) => JoinKind::Range(section.into()),
+                None => return plan_err!("range join requires a range section"),
+            },
+            _ => JoinKind::Equi,
         };
 
         let left_cols = config.lt.iter()
@@ -46,8 +346,51 @@ impl From<&plans::Join<'_>> for JoinConfig {
         let right_cols = config.rt.iter()
             .map(|&c| c.into())
             .collect();
-        
-        Self { join_type, left_cols, right_cols }
+        // Columns in both inputs are told apart with a suffix unless the plan
+        // affixes them itself.
+        let ((lp, rp), (ls, rs)) = match (config.prefixes, config.suffixes) {
+            (None, None) => (("", ""), ("_left", "_right")),
+            (prefixes, suffixes) => (prefixes.unwrap_or_default(), suffixes.unwrap_or_default()),
+        };
+        if lp == rp && ls == rs {
+            return plan_err!("join: prefixes and suffixes must tell the two inputs apart")
+        }
+
+        Ok(Self {
+            kind,
+            join_type,
+            left_cols,
+            right_cols,
+            on: config.on.as_ref().map(|e| convert(e, macros)).transpose()?,
+            cross: config.cross.unwrap_or(false),
+            null_equal: config.null_equal.unwrap_or(false),
+            prefixes: (lp.into(), rp.into()),
+            suffixes: (ls.into(), rs.into()),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct JsonParseConfig {
+    pub column: String,
+    pub dtype: DataType,
+    pub flatten: bool,
+}
+impl TryFrom<&plans::JsonParse<'_>> for JsonParseConfig {
+    type Error = DataFusionError;
+
+    fn try_from(config: &plans::JsonParse) -> Result<Self>
+    {
+        let dtype: DataType = config.schema.clone().into();
+        if !matches!(dtype, DataType::Struct(_)) {
+            return plan_err!("json_parse schema must be a struct type, found {dtype}")
+        }
+
+        Ok(Self {
+            dtype,
+            column: config.column.into(),
+            flatten: config.flatten.unwrap_or(false),
+        })
     }
 }
 
@@ -55,10 +398,65 @@ impl From<&plans::Join<'_>> for JoinConfig {
 pub struct MapConfig {
     pub exprs: Vec<Expr>,
 }
-impl From<&plans::Map<'_>> for MapConfig {
-    fn from(config: &plans::Map) -> Self
+impl TryFrom<(&plans::Map<'_>, &Macros)> for MapConfig {
+    type Error = DataFusionError;
+
+    fn try_from((config, macros): (&plans::Map, &Macros)) -> Result<Self>
     {
-        Self { exprs: config.exprs.iter().map(convert).collect() }
+        let exprs = config.exprs.iter().map(|e| convert(e, macros)).collect::<Result<_>>()?;
+
+        Ok(Self { exprs })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ProfileConfig {
+    pub approximate: bool,
+    pub top_k: usize,
+    pub bins: usize,
+    pub report: Option<String>,
+}
+impl From<&plans::Profile<'_>> for ProfileConfig {
+    fn from(config: &plans::Profile) -> Self
+    {
+        Self {
+            approximate: config.approximate.unwrap_or(false),
+            top_k: config.top_k.unwrap_or(5),
+            bins: config.bins.unwrap_or(10),
+            report: config.report.map(|s| s.into()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct QueryConfig {
+    pub stmt: String,
+}
+impl From<&plans::Query<'_>> for QueryConfig {
+    fn from(config: &plans::Query) -> Self
+    {
+        Self { stmt: config.stmt.to_string() }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Scd2Config {
+    pub keys: Vec<String>,
+    pub tracked: Vec<String>,
+    pub effective: Expr,
+    pub close_missing: bool,
+}
+impl TryFrom<(&plans::Scd2<'_>, &Macros)> for Scd2Config {
+    type Error = DataFusionError;
+
+    fn try_from((config, macros): (&plans::Scd2, &Macros)) -> Result<Self>
+    {
+        Ok(Self {
+            keys: config.keys.iter().map(|&k| k.into()).collect(),
+            tracked: config.tracked.iter().map(|&c| c.into()).collect(),
+            effective: config.effective.as_ref().map_or_else(|| Ok(now()), |e| convert(e, macros))?,
+            close_missing: config.close_missing.unwrap_or(false),
+        })
     }
 }
 
@@ -85,44 +483,147 @@ impl From<&plans::Select<'_>> for SelectConfig {
 pub struct SortConfig {
     pub exprs: Vec<SortExpr>,
 }
-impl From<&plans::Sort<'_>> for SortConfig {
-    fn from(config: &plans::Sort) -> Self
+impl TryFrom<(&plans::Sort<'_>, &Macros)> for SortConfig {
+    type Error = DataFusionError;
+
+    fn try_from((config, macros): (&plans::Sort, &Macros)) -> Result<Self>
     {
         let exprs = config.exprs.iter()
-            .map(|se| {
-                let asc  = se.asc.unwrap_or(true);
-                let expr = convert(&se.expr);
-                let nulls_first = se.nulls_first.unwrap_or(false);
-                SortExpr::new(expr, asc, nulls_first)
-            })
-            .collect();
+            .map(|se| sort_expr(se, macros))
+            .collect::<Result<_>>()?;
 
-        Self { exprs }
+        Ok(Self { exprs })
     }
 }
 
+fn sort_expr(se: &plans::SortExpression, macros: &Macros) -> Result<SortExpr>
+{
+    let asc  = se.asc.unwrap_or(true);
+    let expr = convert(&se.expr, macros)?;
+    let nulls_first = se.nulls_first.unwrap_or(false);
+
+    Ok(SortExpr::new(expr, asc, nulls_first))
+}
+
 #[derive(Clone, Debug)]
 pub struct SummarizeConfig {
     pub aggr: Vec<Expr>,
     pub group: Vec<Expr>,
 }
-impl From<&plans::Summarize<'_>> for SummarizeConfig {
-    fn from(config: &plans::Summarize) -> Self
+impl TryFrom<(&plans::Summarize<'_>, &Macros)> for SummarizeConfig {
+    type Error = DataFusionError;
+
+    fn try_from((config, macros): (&plans::Summarize, &Macros)) -> Result<Self>
     {
-        Self {
-            aggr: config.aggr.iter().map(convert).collect(),
-            group: config.group.iter().map(convert).collect(),
-        }
+        Ok(Self {
+            aggr: config.aggr.iter().map(|e| convert(e, macros)).collect::<Result<_>>()?,
+            group: config.group.iter().map(|e| convert(e, macros)).collect::<Result<_>>()?,
+        })
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct UnionConfig {
     pub distinct: bool,
+    pub by_name: bool,
+    pub allow_utf8: bool,
+    pub source: Option<String>,
 }
 impl From<&plans::Union<'_>> for UnionConfig {
     fn from(config: &plans::Union) -> Self
     {
-        Self { distinct: config.distinct.unwrap_or(false) }
+        Self {
+            distinct: config.distinct.unwrap_or(false),
+            by_name: config.by_name.unwrap_or(false),
+            allow_utf8: config.allow_utf8.unwrap_or(false),
+            source: config.source.map(|s| s.into()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UnnestConfig {
+    pub columns: Vec<String>,
+    pub preserve_nulls: bool,
+}
+impl From<&plans::Unnest<'_>> for UnnestConfig {
+    fn from(config: &plans::Unnest) -> Self
+    {
+        Self {
+            columns: config.columns.iter().map(|&c| c.into()).collect(),
+            preserve_nulls: config.preserve_nulls.unwrap_or(true),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum RuleCheck {
+    Row(Expr),
+    Unique(Vec<Expr>),
+    RowCount(Option<usize>, Option<usize>),
+    Schema(Vec<Field>),
+}
+
+#[derive(Clone, Debug)]
+pub struct RuleConfig {
+    pub name: String,
+    pub severity: Severity,
+    pub check: RuleCheck,
+}
+impl TryFrom<(&plans::Rule<'_>, &Macros)> for RuleConfig {
+    type Error = DataFusionError;
+
+    fn try_from((config, macros): (&plans::Rule, &Macros)) -> Result<Self>
+    {
+        use plans::Check::*;
+
+        let convert = |e: &Expression| crate::expr::convert(e, macros);
+        let check = match &config.check {
+            not_null(c) => RuleCheck::Row(ident(*c).is_null()),
+            unique(cols) => RuleCheck::Unique(cols.iter().map(|&c| ident(c)).collect()),
+            range { column, min, max } => {
+                let below = min.as_ref().map(convert).transpose()?.map(|v| ident(*column).lt(v));
+                let above = max.as_ref().map(convert).transpose()?.map(|v| ident(*column).gt(v));
+                let outside = below.into_iter().chain(above).reduce(or).unwrap_or(lit(false));
+                RuleCheck::Row(outside)
+            },
+            regex { column, pattern } =>
+                RuleCheck::Row(binary_expr(ident(*column), Operator::RegexNotMatch, lit(*pattern))),
+            allowed { column, values } => {
+                let values = values.iter().map(convert).collect::<Result<_>>()?;
+                RuleCheck::Row(ident(*column).in_list(values, true))
+            },
+            expr(e) => RuleCheck::Row(convert(e)?.is_not_true()),
+            row_count { min, max } => RuleCheck::RowCount(*min, *max),
+            schema(fields) => RuleCheck::Schema(fields.iter()
+                .map(|field| Field::new(
+                    field.column,
+                    field.variant.clone().into(),
+                    field.nullable.unwrap_or(true)
+                ))
+                .collect()),
+        };
+
+        Ok(Self {
+            check,
+            name: config.name.into(),
+            severity: config.severity.unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ValidateConfig {
+    pub rules: Vec<RuleConfig>,
+}
+impl TryFrom<(&plans::Validate<'_>, &Macros)> for ValidateConfig {
+    type Error = DataFusionError;
+
+    fn try_from((config, macros): (&plans::Validate, &Macros)) -> Result<Self>
+    {
+        let rules = config.rules.iter().map(|r| (r, macros).try_into()).collect::<Result<_>>()?;
+
+        Ok(Self { rules })
     }
 }
@@ -1,23 +1,64 @@
-use datafusion::logical_expr::Operator;
-use datafusion::functions_aggregate::expr_fn::{avg, max, min, stddev, sum};
+use std::fmt;
+
+use datafusion::arrow::datatypes::{DataType, IntervalUnit, TimeUnit};
+use datafusion::common::{plan_err, Column, ScalarValue};
+use datafusion::error::Result;
+use datafusion::logical_expr::{ExprFunctionExt, Operator};
+use datafusion::logical_expr::conditional_expressions::CaseBuilder;
+use datafusion::functions::{datetime, regex, unicode};
+use datafusion::functions_aggregate::approx_percentile_cont::approx_percentile_cont_udaf;
+use datafusion::functions_aggregate::expr_fn::{
+    avg, count, count_distinct, max, median, min, stddev, sum, var_sample
+};
+use datafusion::functions_aggregate::first_last::{first_value_udaf, last_value_udaf};
+use datafusion::functions_aggregate::string_agg::string_agg_udaf;
 use datafusion::prelude::*;
-use serde::Deserialize;
+use serde::{de, Deserialize, Deserializer};
 
+use crate::{json, parser};
+use crate::macros::Macros;
 use crate::plans::SchemaDataType;
 
 #[derive(Debug, Deserialize)]
+#[serde(remote = "Self")]
 #[allow(non_camel_case_types)]
 pub enum Expression<'a> {
     col(&'a str),
+    qcol(&'a str, &'a str),
     f32(f32),
     f64(f64),
     i32(i32),
     i64(i64),
     str(&'a str),
+    bool(bool),
+    null(()),
+    u64(u64),
+    decimal(&'a str),
+    date(&'a str),
+    timestamp(&'a str),
+    interval(&'a str),
     abs(Box<Expression<'a>>),
     acos(Box<Expression<'a>>),
     asin(Box<Expression<'a>>),
     atan(Box<Expression<'a>>),
+    atan2(Box<[Expression<'a>;2]>),
+    sin(Box<Expression<'a>>),
+    cos(Box<Expression<'a>>),
+    tan(Box<Expression<'a>>),
+    cot(Box<Expression<'a>>),
+    degrees(Box<Expression<'a>>),
+    radians(Box<Expression<'a>>),
+    sqrt(Box<Expression<'a>>),
+    exp(Box<Expression<'a>>),
+    ln(Box<Expression<'a>>),
+    log10(Box<Expression<'a>>),
+    log(Box<[Expression<'a>;2]>),
+    pow(Box<[Expression<'a>;2]>),
+    round(Vec<Expression<'a>>),
+    trunc(Vec<Expression<'a>>),
+    floor(Box<Expression<'a>>),
+    ceil(Box<Expression<'a>>),
+    sign(Box<Expression<'a>>),
     not(Box<Expression<'a>>),
     eq(Box<[Expression<'a>;2]>),
     ne(Box<[Expression<'a>;2]>),
@@ -29,13 +70,76 @@ pub enum Expression<'a> {
     sub(Box<[Expression<'a>;2]>),
     mul(Box<[Expression<'a>;2]>),
     div(Box<[Expression<'a>;2]>),
-    avg(Vec<Expression<'a>>),
     and(Vec<Expression<'a>>),
     or(Vec<Expression<'a>>),
-    min(Vec<Expression<'a>>),
-    max(Vec<Expression<'a>>),
-    sum(Vec<Expression<'a>>),
-    stddev(Vec<Expression<'a>>),
+    avg(Box<Expression<'a>>),
+    min(Box<Expression<'a>>),
+    max(Box<Expression<'a>>),
+    sum(Box<Expression<'a>>),
+    stddev(Box<Expression<'a>>),
+    var(Box<Expression<'a>>),
+    count(Box<Expression<'a>>),
+    count_distinct(Box<Expression<'a>>),
+    median(Box<Expression<'a>>),
+    // Approximate, as it's computed from a t-digest by approx_percentile_cont
+    // rather than by sorting every value like median.
+    percentile(Box<Expression<'a>>, f64),
+    first(Box<Expression<'a>>, Vec<Expression<'a>>),
+    last(Box<Expression<'a>>, Vec<Expression<'a>>),
+    string_agg(Box<Expression<'a>>, &'a str),
+    filter(Box<Expression<'a>>, Box<Expression<'a>>),
+    like(Box<[Expression<'a>;2]>),
+    ilike(Box<[Expression<'a>;2]>),
+    not_like(Box<[Expression<'a>;2]>),
+    not_ilike(Box<[Expression<'a>;2]>),
+    upper(Box<Expression<'a>>),
+    lower(Box<Expression<'a>>),
+    length(Box<Expression<'a>>),
+    trim(Vec<Expression<'a>>),
+    ltrim(Vec<Expression<'a>>),
+    rtrim(Vec<Expression<'a>>),
+    concat(Vec<Expression<'a>>),
+    substr(Vec<Expression<'a>>),
+    replace(Box<[Expression<'a>;3]>),
+    split_part(Box<[Expression<'a>;3]>),
+    starts_with(Box<[Expression<'a>;2]>),
+    ends_with(Box<[Expression<'a>;2]>),
+    lpad(Vec<Expression<'a>>),
+    rpad(Vec<Expression<'a>>),
+    regexp_like(Vec<Expression<'a>>),
+    regexp_match(Vec<Expression<'a>>),
+    regexp_replace(Vec<Expression<'a>>),
+    is_null(Box<Expression<'a>>),
+    is_not_null(Box<Expression<'a>>),
+    coalesce(Vec<Expression<'a>>),
+    nullif(Box<[Expression<'a>;2]>),
+    in_list(Box<Expression<'a>>, Vec<Expression<'a>>),
+    not_in(Box<Expression<'a>>, Vec<Expression<'a>>),
+    between(Box<[Expression<'a>;3]>),
+    not_between(Box<[Expression<'a>;3]>),
+    now(()),
+    to_date(Vec<Expression<'a>>),
+    to_timestamp(Vec<Expression<'a>>),
+    format(Box<[Expression<'a>;2]>),
+    date_trunc(&'a str, Box<Expression<'a>>),
+    date_part(&'a str, Box<Expression<'a>>),
+    date_diff(&'a str, Box<[Expression<'a>;2]>),
+    at_timezone(Box<Expression<'a>>, &'a str),
+    local_time(Box<Expression<'a>>),
+    field(Box<Expression<'a>>, &'a str),
+    list_index(Box<[Expression<'a>;2]>),
+    list_length(Box<Expression<'a>>),
+    json_parse(Box<Expression<'a>>, SchemaDataType),
+    json_extract(Box<Expression<'a>>, &'a str),
+    call(&'a str, Vec<Expression<'a>>),
+    apply(&'a str, Vec<Expression<'a>>),
+    case {
+        expr: Option<Box<Expression<'a>>>,
+        when: Vec<WhenThen<'a>>,
+
+        #[serde(rename(deserialize = "else"))]
+        otherwise: Option<Box<Expression<'a>>>,
+    },
 
     #[serde(rename(deserialize = "true"))]
     is_true(Box<Expression<'a>>),
@@ -49,45 +153,512 @@ pub enum Expression<'a> {
     #[serde(rename(deserialize = "mod"))]
     modulus(Box<[Expression<'a>;2]>),
 
+    alias(Box<Expression<'a>>, &'a str),
     cast(Box<Expression<'a>>, SchemaDataType),
 }
 
-pub fn convert(expr: &Expression) -> Expr
+// Expressions are either a tagged object, {"gt": [...]}, or text such as
+// "Horsepower > 200" which is parsed into the same tree. Text is borrowed from
+// the plan, so it can't contain JSON escapes: quote identifiers with backticks,
+// "`First Name` = 'Ann'", rather than with escaped double quotes.
+impl<'de: 'a, 'a> Deserialize<'de> for Expression<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>
+    {
+        struct Visitor;
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Expression<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result
+            {
+                f.write_str("an expression object or expression text")
+            }
+
+            fn visit_borrowed_str<E: de::Error>(self, text: &'de str) -> Result<Self::Value, E>
+            {
+                parser::parse(text).map_err(|e| E::custom(format!("{e} in {text:?}")))
+            }
+
+            fn visit_str<E: de::Error>(self, text: &str) -> Result<Self::Value, E>
+            {
+                Err(E::custom(format!(
+                    "expression {text:?} can't contain JSON escapes, quote names with backticks"
+                )))
+            }
+
+            fn visit_map<M: de::MapAccess<'de>>(self, map: M) -> Result<Self::Value, M::Error>
+            {
+                Expression::deserialize(de::value::MapAccessDeserializer::new(map))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WhenThen<'a> {
+    #[serde(borrow)]
+    pub when: Expression<'a>,
+    pub then: Expression<'a>,
+}
+
+impl<'a> Expression<'a> {
+    pub fn name(&self) -> &'static str
+    {
+        use Expression::*;
+
+        match self {
+            col(..)            => "col",
+            qcol(..)           => "qcol",
+            f32(..)            => "f32",
+            f64(..)            => "f64",
+            i32(..)            => "i32",
+            i64(..)            => "i64",
+            str(..)            => "str",
+            bool(..)           => "bool",
+            null(..)           => "null",
+            u64(..)            => "u64",
+            decimal(..)        => "decimal",
+            date(..)           => "date",
+            timestamp(..)      => "timestamp",
+            interval(..)       => "interval",
+            abs(..)            => "abs",
+            acos(..)           => "acos",
+            asin(..)           => "asin",
+            atan(..)           => "atan",
+            atan2(..)          => "atan2",
+            sin(..)            => "sin",
+            cos(..)            => "cos",
+            tan(..)            => "tan",
+            cot(..)            => "cot",
+            degrees(..)        => "degrees",
+            radians(..)        => "radians",
+            sqrt(..)           => "sqrt",
+            exp(..)            => "exp",
+            ln(..)             => "ln",
+            log10(..)          => "log10",
+            log(..)            => "log",
+            pow(..)            => "pow",
+            round(..)          => "round",
+            trunc(..)          => "trunc",
+            floor(..)          => "floor",
+            ceil(..)           => "ceil",
+            sign(..)           => "sign",
+            not(..)            => "not",
+            eq(..)             => "eq",
+            ne(..)             => "ne",
+            gt(..)             => "gt",
+            gte(..)            => "gte",
+            lt(..)             => "lt",
+            lte(..)            => "lte",
+            add(..)            => "add",
+            sub(..)            => "sub",
+            mul(..)            => "mul",
+            div(..)            => "div",
+            and(..)            => "and",
+            or(..)             => "or",
+            avg(..)            => "avg",
+            min(..)            => "min",
+            max(..)            => "max",
+            sum(..)            => "sum",
+            stddev(..)         => "stddev",
+            var(..)            => "var",
+            count(..)          => "count",
+            count_distinct(..) => "count_distinct",
+            median(..)         => "median",
+            percentile(..)     => "percentile",
+            first(..)          => "first",
+            last(..)           => "last",
+            string_agg(..)     => "string_agg",
+            filter(..)         => "filter",
+            like(..)           => "like",
+            ilike(..)          => "ilike",
+            not_like(..)       => "not_like",
+            not_ilike(..)      => "not_ilike",
+            upper(..)          => "upper",
+            lower(..)          => "lower",
+            length(..)         => "length",
+            trim(..)           => "trim",
+            ltrim(..)          => "ltrim",
+            rtrim(..)          => "rtrim",
+            concat(..)         => "concat",
+            substr(..)         => "substr",
+            replace(..)        => "replace",
+            split_part(..)     => "split_part",
+            starts_with(..)    => "starts_with",
+            ends_with(..)      => "ends_with",
+            lpad(..)           => "lpad",
+            rpad(..)           => "rpad",
+            regexp_like(..)    => "regexp_like",
+            regexp_match(..)   => "regexp_match",
+            regexp_replace(..) => "regexp_replace",
+            is_null(..)        => "is_null",
+            is_not_null(..)    => "is_not_null",
+            coalesce(..)       => "coalesce",
+            nullif(..)         => "nullif",
+            in_list(..)        => "in_list",
+            not_in(..)         => "not_in",
+            between(..)        => "between",
+            not_between(..)    => "not_between",
+            now(..)            => "now",
+            to_date(..)        => "to_date",
+            to_timestamp(..)   => "to_timestamp",
+            format(..)         => "format",
+            date_trunc(..)     => "date_trunc",
+            date_part(..)      => "date_part",
+            date_diff(..)      => "date_diff",
+            at_timezone(..)    => "at_timezone",
+            local_time(..)     => "local_time",
+            field(..)          => "field",
+            list_index(..)     => "list_index",
+            list_length(..)    => "list_length",
+            json_parse(..)     => "json_parse",
+            json_extract(..)   => "json_extract",
+            call(..)           => "call",
+            apply(..)          => "apply",
+            case { .. }        => "case",
+            is_true(..)        => "true",
+            is_false(..)       => "false",
+            product(..)        => "prod",
+            modulus(..)        => "mod",
+            alias(..)          => "alias",
+            cast(..)           => "cast",
+        }
+    }
+
+    pub fn is_aggregate(&self) -> bool
+    {
+        use Expression::*;
+
+        matches!(self,
+            avg(..) | min(..) | max(..) | sum(..) | stddev(..) | var(..) | count(..)
+                | count_distinct(..) | median(..) | percentile(..) | first(..) | last(..)
+                | string_agg(..)
+        )
+    }
+
+    // The sub-expressions of this node, each with the JSON path suffix that
+    // leads to it, such as ".gt[0]" or ".case.when[1].then".
+    pub fn children(&self) -> Vec<(String, &Expression<'a>)>
+    {
+        use Expression::*;
+
+        let tag = self.name();
+        let item = |i: usize| format!(".{tag}[{i}]");
+        match self {
+            col(..) | str(..) | decimal(..) | date(..) | timestamp(..) | interval(..)
+                | qcol(..) | f32(..) | f64(..) | i32(..) | i64(..) | bool(..) | null(..)
+                | now(..) | u64(..) => vec![],
+            abs(expr) | acos(expr) | asin(expr) | atan(expr) | sin(expr) | cos(expr)
+                | tan(expr) | cot(expr) | degrees(expr) | radians(expr) | sqrt(expr) | exp(expr)
+                | ln(expr) | log10(expr) | floor(expr) | ceil(expr) | sign(expr) | not(expr)
+                | avg(expr) | min(expr) | max(expr) | sum(expr) | stddev(expr) | var(expr)
+                | count(expr) | count_distinct(expr) | median(expr) | upper(expr) | lower(expr)
+                | length(expr) | is_null(expr) | is_not_null(expr) | local_time(expr)
+                | list_length(expr) | is_true(expr) | is_false(expr) =>
+                vec![(format!(".{tag}"), expr.as_ref())],
+            atan2(exprs) | log(exprs) | pow(exprs) | eq(exprs) | ne(exprs) | gt(exprs)
+                | gte(exprs) | lt(exprs) | lte(exprs) | add(exprs) | sub(exprs) | mul(exprs)
+                | div(exprs) | like(exprs) | ilike(exprs) | not_like(exprs) | not_ilike(exprs)
+                | starts_with(exprs) | ends_with(exprs) | nullif(exprs) | format(exprs)
+                | list_index(exprs) | modulus(exprs) =>
+                exprs.iter().enumerate().map(|(i, e)| (item(i), e)).collect(),
+            replace(exprs) | split_part(exprs) | between(exprs) | not_between(exprs) =>
+                exprs.iter().enumerate().map(|(i, e)| (item(i), e)).collect(),
+            round(exprs) | trunc(exprs) | and(exprs) | or(exprs) | trim(exprs)
+                | ltrim(exprs) | rtrim(exprs) | concat(exprs) | substr(exprs) | lpad(exprs)
+                | rpad(exprs) | regexp_like(exprs) | regexp_match(exprs) | regexp_replace(exprs)
+                | coalesce(exprs) | to_date(exprs) | to_timestamp(exprs) | product(exprs) =>
+                exprs.iter().enumerate().map(|(i, e)| (item(i), e)).collect(),
+            string_agg(expr, _) | at_timezone(expr, _) | field(expr, _)
+                | json_extract(expr, _) | alias(expr, _) | percentile(expr, _)
+                | json_parse(expr, _) | cast(expr, _) =>
+                vec![(item(0), expr.as_ref())],
+            date_trunc(_, expr) | date_part(_, expr) => vec![(item(1), expr.as_ref())],
+            date_diff(_, exprs) => exprs.iter().enumerate()
+                .map(|(i, e)| (format!(".{tag}[1][{i}]"), e))
+                .collect(),
+            call(_, exprs) | apply(_, exprs) => exprs.iter().enumerate()
+                .map(|(i, e)| (format!(".{tag}[1][{i}]"), e))
+                .collect(),
+            filter(expr, predicate) =>
+                vec![(item(0), expr.as_ref()), (item(1), predicate.as_ref())],
+            first(expr, list) | last(expr, list) | in_list(expr, list) | not_in(expr, list) =>
+                std::iter::once((item(0), expr.as_ref()))
+                    .chain(list.iter().enumerate().map(|(i, e)| (format!(".{tag}[1][{i}]"), e)))
+                    .collect(),
+            case { expr, when, otherwise } => expr.iter()
+                .map(|e| (".case.expr".to_string(), e.as_ref()))
+                .chain(when.iter().enumerate().flat_map(|(i, arm)| [
+                    (format!(".case.when[{i}].when"), &arm.when),
+                    (format!(".case.when[{i}].then"), &arm.then),
+                ]))
+                .chain(otherwise.iter().map(|e| (".case.else".to_string(), e.as_ref())))
+                .collect(),
+        }
+    }
+}
+
+pub fn convert(expr: &Expression, macros: &Macros) -> Result<Expr>
 {
-    match expr {
+    // Every nested expression is converted with the same plan functions.
+    let convert = |expr: &Expression| self::convert(expr, macros);
+
+    Ok(match expr {
         Expression::f32(v)  => lit(*v),
         Expression::f64(v)  => lit(*v),
         Expression::i32(v)  => lit(*v),
         Expression::i64(v)  => lit(*v),
         Expression::str(v) => lit(*v),
+        Expression::bool(v) => lit(*v),
+        Expression::null(_) => lit(ScalarValue::Null),
+        Expression::u64(v)  => lit(*v),
+        Expression::decimal(v) => decimal(v)?,
+        Expression::date(v) => cast(lit(*v), DataType::Date32),
+        Expression::timestamp(v) => cast(lit(*v), DataType::Timestamp(TimeUnit::Nanosecond, None)),
+        Expression::interval(v)  => cast(lit(*v), DataType::Interval(IntervalUnit::MonthDayNano)),
         Expression::col(v) => col(format!(r#""{v}""#)),
-        Expression::abs(expr)  => abs(convert(expr)),
-        Expression::acos(expr) => acos(convert(expr)),
-        Expression::asin(expr) => atan(convert(expr)),
-        Expression::atan(expr) => atan(convert(expr)),
-        Expression::not(expr)  => not(convert(expr)),
-        Expression::and(exprs) => exprs.iter().map(convert).reduce(and).unwrap(),
-        Expression::or(exprs)  => exprs.iter().map(convert).reduce(or).unwrap(),
-        Expression::avg(exprs) => avg(make_array(exprs.iter().map(convert).collect())),
-        Expression::min(exprs) => min(make_array(exprs.iter().map(convert).collect())),
-        Expression::max(exprs) => max(make_array(exprs.iter().map(convert).collect())),
-        Expression::sum(exprs) => sum(make_array(exprs.iter().map(convert).collect())),
-        Expression::eq(exprs)  => binary_expr(convert(&exprs[0]), Operator::Eq, convert(&exprs[1])),
-        Expression::ne(exprs)  => binary_expr(convert(&exprs[0]), Operator::NotEq, convert(&exprs[1])),
-        Expression::gt(exprs)  => binary_expr(convert(&exprs[0]), Operator::Gt, convert(&exprs[1])),
-        Expression::gte(exprs) => binary_expr(convert(&exprs[0]), Operator::GtEq, convert(&exprs[1])),
-        Expression::lt(exprs)  => binary_expr(convert(&exprs[0]), Operator::Lt, convert(&exprs[1])),
-        Expression::lte(exprs) => binary_expr(convert(&exprs[0]), Operator::LtEq, convert(&exprs[1])),
-        Expression::add(exprs) => binary_expr(convert(&exprs[0]), Operator::Plus, convert(&exprs[1])),
-        Expression::sub(exprs) => binary_expr(convert(&exprs[0]), Operator::Minus, convert(&exprs[1])),
-        Expression::mul(exprs) => binary_expr(convert(&exprs[0]), Operator::Multiply, convert(&exprs[1])),
-        Expression::div(exprs) => binary_expr(convert(&exprs[0]), Operator::Divide, convert(&exprs[1])),
+        Expression::qcol(q, v) => col(Column::new(Some(*q), *v)),
+        Expression::abs(expr)  => abs(convert(expr)?),
+        Expression::acos(expr) => acos(convert(expr)?),
+        Expression::asin(expr) => asin(convert(expr)?),
+        Expression::atan(expr) => atan(convert(expr)?),
+        Expression::not(expr)  => not(convert(expr)?),
+        Expression::and(exprs) => fold("and", exprs, macros, and)?,
+        Expression::or(exprs)  => fold("or", exprs, macros, or)?,
+        Expression::avg(expr)  => avg(convert(expr)?),
+        Expression::min(expr)  => min(convert(expr)?),
+        Expression::max(expr)  => max(convert(expr)?),
+        Expression::sum(expr)  => sum(convert(expr)?),
+        Expression::var(expr)  => var_sample(convert(expr)?),
+        Expression::eq(exprs)  => binary(exprs, Operator::Eq, macros)?,
+        Expression::ne(exprs)  => binary(exprs, Operator::NotEq, macros)?,
+        Expression::gt(exprs)  => binary(exprs, Operator::Gt, macros)?,
+        Expression::gte(exprs) => binary(exprs, Operator::GtEq, macros)?,
+        Expression::lt(exprs)  => binary(exprs, Operator::Lt, macros)?,
+        Expression::lte(exprs) => binary(exprs, Operator::LtEq, macros)?,
+        Expression::add(exprs) => binary(exprs, Operator::Plus, macros)?,
+        Expression::sub(exprs) => binary(exprs, Operator::Minus, macros)?,
+        Expression::mul(exprs) => binary(exprs, Operator::Multiply, macros)?,
+        Expression::div(exprs) => binary(exprs, Operator::Divide, macros)?,
         
-        Expression::is_true(expr)  => is_true(convert(expr)),
-        Expression::is_false(expr) => is_false(convert(expr)),
-        Expression::stddev(exprs)  => stddev(make_array(exprs.iter().map(convert).collect())),
-        Expression::modulus(exprs) => binary_expr(convert(&exprs[0]), Operator::Modulo, convert(&exprs[1])),
-        Expression::product(exprs) => exprs.iter().map(convert).reduce(|a, b| a * b).unwrap(),
-        Expression::cast(expr, dtype) => try_cast(convert(expr), (*dtype).into()),
+        Expression::is_true(expr)  => is_true(convert(expr)?),
+        Expression::is_false(expr) => is_false(convert(expr)?),
+        Expression::stddev(expr)   => stddev(convert(expr)?),
+        Expression::modulus(exprs) => binary(exprs, Operator::Modulo, macros)?,
+        Expression::product(exprs) => fold("prod", exprs, macros, |a, b| a * b)?,
+        Expression::like(exprs)      => convert(&exprs[0])?.like(convert(&exprs[1])?),
+        Expression::ilike(exprs)     => convert(&exprs[0])?.ilike(convert(&exprs[1])?),
+        Expression::not_like(exprs)  => convert(&exprs[0])?.not_like(convert(&exprs[1])?),
+        Expression::not_ilike(exprs) => convert(&exprs[0])?.not_ilike(convert(&exprs[1])?),
+        Expression::upper(expr)      => upper(convert(expr)?),
+        Expression::lower(expr)      => lower(convert(expr)?),
+        Expression::length(expr)     => character_length(convert(expr)?),
+        Expression::trim(exprs)      => btrim(args(exprs, macros)?),
+        Expression::ltrim(exprs)     => ltrim(args(exprs, macros)?),
+        Expression::rtrim(exprs)     => rtrim(args(exprs, macros)?),
+        Expression::concat(exprs)    => concat(args(exprs, macros)?),
+        Expression::substr(exprs)    => unicode::substr().call(args(exprs, macros)?),
+        Expression::lpad(exprs)      => lpad(args(exprs, macros)?),
+        Expression::rpad(exprs)      => rpad(args(exprs, macros)?),
+        Expression::replace(exprs)   =>
+            replace(convert(&exprs[0])?, convert(&exprs[1])?, convert(&exprs[2])?),
+        Expression::split_part(exprs) =>
+            split_part(convert(&exprs[0])?, convert(&exprs[1])?, convert(&exprs[2])?),
+        Expression::starts_with(exprs) => starts_with(convert(&exprs[0])?, convert(&exprs[1])?),
+        Expression::ends_with(exprs)   => ends_with(convert(&exprs[0])?, convert(&exprs[1])?),
+        Expression::regexp_like(exprs)    => regex::regexp_like().call(args(exprs, macros)?),
+        Expression::regexp_match(exprs)   => regex::regexp_match().call(args(exprs, macros)?),
+        Expression::regexp_replace(exprs) => regex::regexp_replace().call(args(exprs, macros)?),
+
+        Expression::atan2(exprs)   => atan2(convert(&exprs[0])?, convert(&exprs[1])?),
+        Expression::sin(expr)      => sin(convert(expr)?),
+        Expression::cos(expr)      => cos(convert(expr)?),
+        Expression::tan(expr)      => tan(convert(expr)?),
+        Expression::cot(expr)      => cot(convert(expr)?),
+        Expression::degrees(expr)  => degrees(convert(expr)?),
+        Expression::radians(expr)  => radians(convert(expr)?),
+        Expression::sqrt(expr)     => sqrt(convert(expr)?),
+        Expression::exp(expr)      => exp(convert(expr)?),
+        Expression::ln(expr)       => ln(convert(expr)?),
+        Expression::log10(expr)    => log10(convert(expr)?),
+        Expression::log(exprs)     => log(convert(&exprs[0])?, convert(&exprs[1])?),
+        Expression::pow(exprs)     => power(convert(&exprs[0])?, convert(&exprs[1])?),
+        Expression::round(exprs)   => round(args(exprs, macros)?),
+        Expression::trunc(exprs)   => trunc(args(exprs, macros)?),
+        Expression::floor(expr)    => floor(convert(expr)?),
+        Expression::ceil(expr)     => ceil(convert(expr)?),
+        Expression::sign(expr)     => signum(convert(expr)?),
+
+        Expression::count(expr)          => count(convert(expr)?),
+        Expression::count_distinct(expr) => count_distinct(convert(expr)?),
+        Expression::median(expr)         => median(convert(expr)?),
+        Expression::percentile(expr, p)  =>
+            approx_percentile_cont_udaf().call(vec![convert(expr)?, lit(*p)]),
+        Expression::first(expr, order)   =>
+            ordered(first_value_udaf().call(vec![convert(expr)?]), order, macros)?,
+        Expression::last(expr, order)    =>
+            ordered(last_value_udaf().call(vec![convert(expr)?]), order, macros)?,
+        Expression::string_agg(expr, delimiter) =>
+            string_agg_udaf().call(vec![convert(expr)?, lit(*delimiter)]),
+        Expression::filter(expr, predicate) =>
+            convert(expr)?.filter(convert(predicate)?).build()?,
+
+        Expression::is_null(expr)     => convert(expr)?.is_null(),
+        Expression::is_not_null(expr) => convert(expr)?.is_not_null(),
+        Expression::coalesce(exprs)   => coalesce(args(exprs, macros)?),
+        Expression::nullif(exprs)     => nullif(convert(&exprs[0])?, convert(&exprs[1])?),
+        Expression::in_list(expr, list) => in_list(convert(expr)?, args(list, macros)?, false),
+        Expression::not_in(expr, list)  => in_list(convert(expr)?, args(list, macros)?, true),
+        Expression::between(exprs) =>
+            convert(&exprs[0])?.between(convert(&exprs[1])?, convert(&exprs[2])?),
+        Expression::not_between(exprs) =>
+            convert(&exprs[0])?.not_between(convert(&exprs[1])?, convert(&exprs[2])?),
+        Expression::case { expr, when, otherwise } => case_expr(expr, when, otherwise, macros)?,
+
+        Expression::now(_)              => now(),
+        Expression::to_date(exprs)      => to_date(args(exprs, macros)?),
+        Expression::to_timestamp(exprs) => to_timestamp(args(exprs, macros)?),
+        Expression::format(exprs)       => to_char(convert(&exprs[0])?, convert(&exprs[1])?),
+        Expression::date_trunc(part, expr) => date_trunc(lit(*part), convert(expr)?),
+        Expression::date_part(part, expr)  => date_part(lit(*part), convert(expr)?),
+        Expression::date_diff(unit, exprs) =>
+            date_diff(unit, convert(&exprs[0])?, convert(&exprs[1])?)?,
+        Expression::at_timezone(expr, tz) =>
+            cast(convert(expr)?, DataType::Timestamp(TimeUnit::Nanosecond, Some((*tz).into()))),
+        Expression::local_time(expr) => datetime::to_local_time().call(vec![convert(expr)?]),
+
+        Expression::field(expr, name)     => get_field(convert(expr)?, *name),
+        Expression::list_index(exprs)     =>
+            array_element(convert(&exprs[0])?, convert(&exprs[1])?),
+        Expression::list_length(expr)     => array_length(convert(expr)?),
+        Expression::json_parse(expr, dtype) =>
+            json::json_parse(dtype.clone().into())?.call(vec![convert(expr)?]),
+        Expression::json_extract(expr, path) =>
+            json::json_extract(path)?.call(vec![convert(expr)?]),
+        Expression::call(name, exprs) => match macros.udf(name) {
+            Some(udf) => udf.call(args(exprs, macros)?),
+            None => return plan_err!("unknown udf '{name}'"),
+        },
+        Expression::apply(name, exprs) => macros.expand(name, args(exprs, macros)?)?,
+
+        Expression::alias(expr, name) => convert(expr)?.alias(*name),
+        Expression::cast(expr, dtype) => try_cast(convert(expr)?, dtype.clone().into()),
+    })
+}
+
+fn args(exprs: &[Expression], macros: &Macros) -> Result<Vec<Expr>>
+{
+    exprs.iter().map(|e| convert(e, macros)).collect()
+}
+
+fn binary(exprs: &[Expression; 2], op: Operator, macros: &Macros) -> Result<Expr>
+{
+    Ok(binary_expr(convert(&exprs[0], macros)?, op, convert(&exprs[1], macros)?))
+}
+
+fn fold(
+    name: &str,
+    exprs: &[Expression],
+    macros: &Macros,
+    f: impl Fn(Expr, Expr) -> Expr
+) -> Result<Expr>
+{
+    match args(exprs, macros)?.into_iter().reduce(f) {
+        Some(expr) => Ok(expr),
+        None => plan_err!("{name} requires at least one expression"),
+    }
+}
+
+fn decimal(text: &str) -> Result<Expr>
+{
+    let (whole, frac) = text.split_once('.').unwrap_or((text, ""));
+    let digits = format!("{whole}{frac}");
+    let Ok(value) = digits.parse::<i128>() else {
+        return plan_err!("invalid decimal literal '{text}'")
+    };
+    let scale = frac.len() as i8;
+    let precision = digits.trim_start_matches(['-', '+']).len().max(frac.len()).max(1) as u8;
+
+    Ok(lit(ScalarValue::Decimal128(Some(value), precision, scale)))
+}
+
+fn ordered(aggr: Expr, order: &[Expression], macros: &Macros) -> Result<Expr>
+{
+    if order.is_empty() {
+        Ok(aggr)
+    } else {
+        let order = order.iter()
+            .map(|e| Ok(convert(e, macros)?.sort(true, false)))
+            .collect::<Result<_>>()?;
+        aggr.order_by(order).build()
+    }
+}
+
+// Counts the whole units elapsed from start to end, truncated toward zero
+// for every unit. A month has elapsed once the end reaches the same day and
+// time in a later month, so 2020-01-31 to 2020-02-29 is zero months, and a
+// year is twelve such months.
+fn date_diff(unit: &str, start: Expr, end: Expr) -> Result<Expr>
+{
+    let part = |p: &str, e: &Expr| date_part(lit(p), e.clone());
+    let seconds = |n: i64| cast(
+        (part("epoch", &end) - part("epoch", &start)) / lit(n),
+        DataType::Int64
+    );
+    // Calendar months crossed, less one where the end is not as far into
+    // its month as the start is into its own.
+    let months = || {
+        let index = |e: &Expr| cast(part("year", e) * lit(12) + part("month", e), DataType::Int64);
+        let month = |e: &Expr| date_trunc(lit("month"), e.clone());
+        let offset = |e: &Expr| part("epoch", e) - part("epoch", &month(e));
+        let crossed = index(&end) - index(&start);
+        let (from, to) = (offset(&start), offset(&end));
+
+        when(
+            crossed.clone().gt(lit(0i64)).and(to.clone().lt(from.clone())),
+            crossed.clone() - lit(1i64)
+        )
+        .when(
+            crossed.clone().lt(lit(0i64)).and(to.gt(from)),
+            crossed.clone() + lit(1i64)
+        )
+        .otherwise(crossed)
+    };
+
+    Ok(match unit {
+        "second" => seconds(1),
+        "minute" => seconds(60),
+        "hour"   => seconds(3600),
+        "day"    => seconds(86400),
+        "week"   => seconds(604800),
+        "month"  => months()?,
+        "year"   => months()? / lit(12i64),
+        _ => return plan_err!("date_diff: unknown unit '{unit}'"),
+    })
+}
+
+fn case_expr(
+    expr: &Option<Box<Expression>>,
+    arms: &[WhenThen],
+    otherwise: &Option<Box<Expression>>,
+    macros: &Macros
+) -> Result<Expr>
+{
+    if arms.is_empty() {
+        return plan_err!("case requires at least one when arm")
+    }
+
+    let mut builder = match expr {
+        Some(expr) => case(convert(expr, macros)?),
+        None => CaseBuilder::new(None, vec![], vec![], None),
+    };
+    for arm in arms {
+        builder = builder.when(convert(&arm.when, macros)?, convert(&arm.then, macros)?);
+    }
+
+    match otherwise {
+        Some(expr) => builder.otherwise(convert(expr, macros)?),
+        None => builder.end(),
     }
 }
@@ -0,0 +1,133 @@
+use std::collections::{BTreeSet, HashMap};
+
+use strsim::{jaro_winkler, normalized_levenshtein};
+
+use crate::config::FuzzyMatchConfig;
+use crate::plans::Similarity;
+
+#[derive(Debug)]
+pub struct Record {
+    pub key: String,
+    pub block: Vec<Option<String>>,
+    pub values: Vec<Option<String>>,
+}
+
+#[derive(Debug)]
+pub struct Match {
+    pub left: usize,
+    pub right: usize,
+    pub score: f64,
+}
+
+pub fn similarity(algorithm: Similarity, a: &str, b: &str) -> f64
+{
+    match algorithm {
+        Similarity::levenshtein  => normalized_levenshtein(a, b),
+        Similarity::jaro_winkler => jaro_winkler(a, b),
+        Similarity::token_set    => token_set(a, b),
+    }
+}
+
+fn token_set(a: &str, b: &str) -> f64
+{
+    let ta = a.split_whitespace().collect::<BTreeSet<_>>();
+    let tb = b.split_whitespace().collect::<BTreeSet<_>>();
+
+    let words = |set: Vec<&str>| set.join(" ");
+    let common = words(ta.intersection(&tb).copied().collect());
+    let only_a = words(ta.difference(&tb).copied().collect());
+    let only_b = words(tb.difference(&ta).copied().collect());
+
+    let combine = |rest: &str| [common.as_str(), rest].iter()
+        .filter(|s| !s.is_empty())
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let sa = combine(&only_a);
+    let sb = combine(&only_b);
+
+    normalized_levenshtein(&common, &sa)
+        .max(normalized_levenshtein(&common, &sb))
+        .max(normalized_levenshtein(&sa, &sb))
+}
+
+pub fn score(config: &FuzzyMatchConfig, left: &Record, right: &Record) -> f64
+{
+    let total = config.fields.iter().map(|f| f.weight).sum::<f64>();
+    let sum = config.fields.iter().enumerate()
+        .map(|(i, field)| match (&left.values[i], &right.values[i]) {
+            (Some(a), Some(b)) if config.ignore_case =>
+                field.weight * similarity(field.algorithm, &a.to_lowercase(), &b.to_lowercase()),
+            (Some(a), Some(b)) => field.weight * similarity(field.algorithm, a, b),
+            _ => 0.0,
+        })
+        .sum::<f64>();
+
+    if total > 0.0 { sum / total } else { 0.0 }
+}
+
+// Compares every left record with the right records sharing its blocking
+// key. Without a right side the left records are matched against each
+// other, with each pair reported once.
+pub fn find_matches(
+    config: &FuzzyMatchConfig,
+    left: &[Record],
+    right: Option<&[Record]>
+) -> Vec<Match>
+{
+    let candidates = right.unwrap_or(left);
+    let mut blocks: HashMap<&[Option<String>], Vec<usize>> = HashMap::new();
+    candidates.iter().enumerate()
+        .for_each(|(i, rec)| blocks.entry(rec.block.as_slice()).or_default().push(i));
+
+    let mut matches = vec![];
+    for (i, rec) in left.iter().enumerate() {
+        let Some(block) = blocks.get(rec.block.as_slice()) else { continue };
+        for &j in block {
+            if right.is_none() && j <= i {
+                continue
+            }
+            let score = score(config, rec, &candidates[j]);
+            if score >= config.threshold {
+                matches.push(Match { left: i, right: j, score })
+            }
+        }
+    }
+
+    matches
+}
+
+// Assigns a cluster number to each match by joining matched records into
+// connected groups. Clusters are numbered in order of first appearance.
+pub fn clusters(matches: &[Match], offset: usize) -> Vec<u64>
+{
+    fn find(parent: &mut HashMap<usize, usize>, n: usize) -> usize
+    {
+        let p = *parent.entry(n).or_insert(n);
+        if p == n {
+            n
+        } else {
+            let root = find(parent, p);
+            parent.insert(n, root);
+            root
+        }
+    }
+
+    let mut parent = HashMap::new();
+    for m in matches {
+        let a = find(&mut parent, m.left);
+        let b = find(&mut parent, m.right + offset);
+        if a != b {
+            parent.insert(a, b);
+        }
+    }
+
+    let mut ids = HashMap::new();
+    matches.iter()
+        .map(|m| {
+            let root = find(&mut parent, m.left);
+            let next = ids.len() as u64;
+            *ids.entry(root).or_insert(next)
+        })
+        .collect()
+}
@@ -0,0 +1,168 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use datafusion::arrow::array::{new_empty_array, ArrayRef, AsArray, StringArray, StructArray};
+use datafusion::arrow::buffer::NullBuffer;
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, Schema};
+use datafusion::arrow::json::ReaderBuilder;
+use datafusion::common::{plan_datafusion_err, plan_err};
+use datafusion::error::Result;
+use datafusion::logical_expr::{ColumnarValue, ScalarUDF, ScalarUDFImpl, Signature, Volatility};
+use serde_json::Value;
+
+// Parses a JSON string column into a struct of the declared type. Null
+// strings give null structs and missing keys give null fields.
+#[derive(Debug)]
+pub struct JsonParse {
+    dtype: DataType,
+    signature: Signature,
+}
+
+pub fn json_parse(dtype: DataType) -> Result<ScalarUDF>
+{
+    if !matches!(dtype, DataType::Struct(_)) {
+        return plan_err!("json_parse requires a struct type, found {dtype}")
+    }
+
+    let signature = Signature::exact(vec![DataType::Utf8], Volatility::Immutable);
+    Ok(ScalarUDF::new_from_impl(JsonParse { dtype, signature }))
+}
+
+impl ScalarUDFImpl for JsonParse {
+    fn as_any(&self) -> &dyn Any { self }
+    fn name(&self) -> &str { "json_parse" }
+    fn signature(&self) -> &Signature { &self.signature }
+
+    fn return_type(&self, _: &[DataType]) -> Result<DataType>
+    {
+        Ok(self.dtype.clone())
+    }
+
+    fn invoke_batch(&self, args: &[ColumnarValue], rows: usize) -> Result<ColumnarValue>
+    {
+        let DataType::Struct(fields) = &self.dtype else {
+            return plan_err!("json_parse requires a struct type")
+        };
+
+        let strings = strings(&args[0], rows)?;
+        let strings = strings.as_string::<i32>();
+        let schema = Arc::new(Schema::new(fields.clone()));
+        let mut decoder = ReaderBuilder::new(schema)
+            .with_batch_size(rows.max(1))
+            .build_decoder()?;
+        for text in strings.iter() {
+            decoder.decode(text.unwrap_or("{}").as_bytes())?;
+            decoder.decode(b"\n")?;
+        }
+
+        // An empty batch decodes nothing, so it gets an empty struct array.
+        let columns = match decoder.flush()? {
+            Some(batch) => batch.columns().to_vec(),
+            None => return Ok(ColumnarValue::Array(new_empty_array(&self.dtype))),
+        };
+        let nulls = NullBuffer::from(strings.iter().map(|s| s.is_some()).collect::<Vec<_>>());
+        let array = StructArray::try_new(fields.clone(), columns, Some(nulls))?;
+
+        Ok(ColumnarValue::Array(Arc::new(array)))
+    }
+}
+
+// Extracts the value at a JSON path such as "$.address.city" or
+// "$.items[0].sku". Strings are returned unquoted, other values as JSON.
+#[derive(Debug)]
+pub struct JsonExtract {
+    path: Vec<Step>,
+    signature: Signature,
+}
+
+#[derive(Debug)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+pub fn json_extract(path: &str) -> Result<ScalarUDF>
+{
+    let path = parse_path(path)
+        .map_err(|e| plan_datafusion_err!("json_extract: {e} in '{path}'"))?;
+    let signature = Signature::exact(vec![DataType::Utf8], Volatility::Immutable);
+
+    Ok(ScalarUDF::new_from_impl(JsonExtract { path, signature }))
+}
+
+impl ScalarUDFImpl for JsonExtract {
+    fn as_any(&self) -> &dyn Any { self }
+    fn name(&self) -> &str { "json_extract" }
+    fn signature(&self) -> &Signature { &self.signature }
+
+    fn return_type(&self, _: &[DataType]) -> Result<DataType>
+    {
+        Ok(DataType::Utf8)
+    }
+
+    fn invoke_batch(&self, args: &[ColumnarValue], rows: usize) -> Result<ColumnarValue>
+    {
+        let strings = strings(&args[0], rows)?;
+        let values = strings.as_string::<i32>().iter()
+            .map(|text| text.and_then(|text| self.extract(text)))
+            .collect::<StringArray>();
+
+        Ok(ColumnarValue::Array(Arc::new(values)))
+    }
+}
+
+impl JsonExtract {
+    fn extract(&self, text: &str) -> Option<String>
+    {
+        let value = serde_json::from_str::<Value>(text).ok()?;
+        let value = self.path.iter().try_fold(&value, |value, step| match step {
+            Step::Key(key)     => value.get(key),
+            Step::Index(index) => value.get(*index),
+        })?;
+
+        match value {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            v => Some(v.to_string()),
+        }
+    }
+}
+
+fn parse_path(path: &str) -> std::result::Result<Vec<Step>, String>
+{
+    let Some(mut rest) = path.strip_prefix('$') else {
+        return Err("path must start with '$'".into())
+    };
+
+    let mut steps = vec![];
+    while !rest.is_empty() {
+        if let Some(tail) = rest.strip_prefix('.') {
+            let end = tail.find(['.', '[']).unwrap_or(tail.len());
+            if end == 0 {
+                return Err("empty key".into())
+            }
+            steps.push(Step::Key(tail[..end].into()));
+            rest = &tail[end..];
+        } else if let Some(tail) = rest.strip_prefix('[') {
+            let end = tail.find(']').ok_or("unclosed '['")?;
+            let inner = &tail[..end];
+            let step = match inner.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')) {
+                Some(key) => Step::Key(key.into()),
+                None => Step::Index(inner.parse().map_err(|_| format!("invalid index '{inner}'"))?),
+            };
+            steps.push(step);
+            rest = &tail[end + 1..];
+        } else {
+            return Err(format!("unexpected '{rest}'"))
+        }
+    }
+
+    Ok(steps)
+}
+
+fn strings(arg: &ColumnarValue, rows: usize) -> Result<ArrayRef>
+{
+    let array = arg.clone().into_array(rows)?;
+    Ok(cast(&array, &DataType::Utf8)?)
+}
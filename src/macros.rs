@@ -0,0 +1,133 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use datafusion::common::plan_err;
+use datafusion::common::tree_node::{Transformed, TreeNode};
+use datafusion::error::Result;
+use datafusion::logical_expr::ScalarUDF;
+use datafusion::prelude::Expr;
+
+use crate::expr::{convert, Expression};
+use crate::plans::Function;
+
+// A plan function converted once, with its parameters left as unqualified
+// columns to be replaced by the arguments of each call.
+#[derive(Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Expr,
+}
+
+// The functions and udfs of a single plan, held by its workflow.
+#[derive(Debug, Default)]
+pub struct Macros {
+    defined: BTreeMap<String, Macro>,
+    udfs: BTreeMap<String, ScalarUDF>,
+}
+impl Macros {
+    // Functions are converted callees first so a body can apply any function
+    // defined before or after it, as long as no function ends up applying itself.
+    pub fn load(functions: &[Function], udfs: BTreeMap<String, ScalarUDF>) -> Result<Macros>
+    {
+        let defined = functions.iter()
+            .map(|f| (f.name, f))
+            .collect::<HashMap<_, _>>();
+        if defined.len() != functions.len() {
+            let mut seen = HashSet::new();
+            let name = functions.iter().find(|f| !seen.insert(f.name)).map(|f| f.name);
+            return plan_err!("function '{}' is defined more than once", name.unwrap_or_default())
+        }
+
+        let mut macros = Macros { udfs, ..Macros::default() };
+        for function in functions {
+            macros.define(function, &defined, &mut vec![])?;
+        }
+
+        Ok(macros)
+    }
+
+    fn define<'a>(
+        &mut self,
+        function: &Function<'a>,
+        defined: &HashMap<&'a str, &Function<'a>>,
+        stack: &mut Vec<&'a str>
+    ) -> Result<()>
+    {
+        let name = function.name;
+        if self.defined.contains_key(name) {
+            return Ok(())
+        }
+        if let Some(start) = stack.iter().position(|&n| n == name) {
+            let mut cycle = stack[start..].to_vec();
+            cycle.push(name);
+            return plan_err!("function cycle: {}", cycle.join(" -> "))
+        }
+
+        stack.push(name);
+        for (callee, count) in applied(&function.body) {
+            let Some(callee) = defined.get(callee) else {
+                return plan_err!("function {name}: unknown function '{callee}'")
+            };
+            if callee.params.len() != count {
+                return plan_err!(
+                    "function {name}: {} takes {} argument(s), found {count}",
+                    callee.name, callee.params.len()
+                )
+            }
+            self.define(callee, defined, stack)?;
+        }
+        stack.pop();
+
+        let params = function.params.iter().map(|&p| p.into()).collect();
+        let body = convert(&function.body, self)?;
+        self.defined.insert(name.into(), Macro { params, body });
+
+        Ok(())
+    }
+
+    pub fn expand(&self, name: &str, args: Vec<Expr>) -> Result<Expr>
+    {
+        let Some(function) = self.defined.get(name) else {
+            return plan_err!("unknown function '{name}'")
+        };
+        if function.params.len() != args.len() {
+            let params = function.params.len();
+            return plan_err!("function {name} takes {params} argument(s), found {}", args.len())
+        }
+
+        let expanded = function.body.clone()
+            .transform(|expr| Ok(match &expr {
+                Expr::Column(column) if column.relation.is_none() => {
+                    match function.params.iter().position(|p| *p == column.name) {
+                        Some(i) => Transformed::yes(args[i].clone()),
+                        None => Transformed::no(expr),
+                    }
+                },
+                _ => Transformed::no(expr),
+            }))?;
+
+        Ok(expanded.data)
+    }
+
+    pub fn udf(&self, name: &str) -> Option<&ScalarUDF>
+    {
+        self.udfs.get(name)
+    }
+
+    pub fn udfs(&self) -> impl Iterator<Item = &ScalarUDF>
+    {
+        self.udfs.values()
+    }
+}
+
+fn applied<'a>(expr: &Expression<'a>) -> Vec<(&'a str, usize)>
+{
+    let mut found = match expr {
+        Expression::apply(name, args) => vec![(*name, args.len())],
+        _ => vec![],
+    };
+    for (_, child) in expr.children() {
+        found.extend(applied(child));
+    }
+
+    found
+}
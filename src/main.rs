@@ -1,13 +1,21 @@
 mod actions;
+mod check;
 mod config;
 mod expr;
+mod fuzzy;
+mod json;
+mod macros;
+mod parser;
 mod plans;
+mod profile;
 mod tool;
+mod wasm;
 mod workflow;
 
 use std::fs;
 use std::path::PathBuf;
 
+use check::CheckError;
 use clap::Parser;
 use plans::Plan;
 use workflow::Workflow;
@@ -19,6 +27,10 @@ struct Args {
     #[arg(short, long, value_name = "PATH")]
     plan: PathBuf,
 
+    /// Check the plan without running it
+    #[arg(short, long)]
+    check: bool,
+
     /// Turn debugging information on
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
@@ -35,10 +47,28 @@ async fn main()
     let plan: Plan = serde_json::from_str(&data).unwrap();
     if args.debug > 1 { println!("{:?}", plan); }
 
-    let wf = Workflow::new(&plan);
+    let wf = Workflow::new(&plan).unwrap_or_else(|errors| fail(&errors));
     if args.debug > 1 { println!("{:?}", wf); }
 
+    let errors = wf.check(&plan).await;
+    if !errors.is_empty() {
+        fail(&errors)
+    }
+    if args.check {
+        println!("Plan OK");
+        return
+    }
+
     let t = Instant::now();
     wf.run(args.debug).await.unwrap();
     println!("Done: {:?}", t.elapsed());
 }
+
+fn fail(errors: &[CheckError]) -> !
+{
+    for error in errors {
+        println!("ERROR {error}");
+    }
+
+    std::process::exit(1)
+}
@@ -0,0 +1,636 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::expr::{Expression, WhenThen};
+use crate::plans::SchemaDataType;
+
+type Result<T> = std::result::Result<T, ParseError>;
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub column: usize,
+    pub message: String,
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{} at column {}", self.message, self.column)
+    }
+}
+impl std::error::Error for ParseError {}
+
+fn error(column: usize, message: impl Into<String>) -> ParseError
+{
+    ParseError { column, message: message.into() }
+}
+
+pub fn parse(text: &str) -> Result<Expression<'_>>
+{
+    let mut parser = Parser { tokens: tokenize(text)?, pos: 0 };
+    let expr = parser.or()?;
+    if !matches!(parser.peek(), Token::End) {
+        return Err(parser.unexpected("end of expression"))
+    }
+
+    Ok(expr)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Token<'a> {
+    Ident(&'a str),
+    Quoted(&'a str),
+    Str(&'a str),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Symbol(&'static str),
+    End,
+}
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self {
+            Token::Ident(s)  => write!(f, "'{s}'"),
+            Token::Quoted(s) => write!(f, "\"{s}\""),
+            Token::Str(s)    => write!(f, "string '{s}'"),
+            Token::Int(v)    => write!(f, "{v}"),
+            Token::UInt(v)   => write!(f, "{v}"),
+            Token::Float(v)  => write!(f, "{v}"),
+            Token::Symbol(s) => write!(f, "'{s}'"),
+            Token::End       => write!(f, "end of input"),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 20] = [
+    "<=", ">=", "<>", "!=", "==",
+    "=", "<", ">", "+", "-", "*", "/", "%", "(", ")", "[", "]", ",", ".", ":",
+];
+
+const RESERVED: [&str; 13] = [
+    "and", "or", "not", "is", "in", "like", "ilike", "between",
+    "when", "then", "else", "end", "as",
+];
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token<'_>)>>
+{
+    let column = |offset: usize| text[..offset].chars().count() + 1;
+    let mut chars = text.char_indices().peekable();
+    let mut tokens = vec![];
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue
+        }
+
+        let token = if c.is_alphabetic() || c == '_' {
+            let end = take_while(&mut chars, text.len(), |c| c.is_alphanumeric() || c == '_');
+            Token::Ident(&text[start..end])
+        } else if c.is_ascii_digit() {
+            let mut end = take_while(&mut chars, text.len(), |c| c.is_ascii_digit());
+            let float = chars.next_if(|&(_, c)| c == '.').is_some();
+            if float {
+                end = take_while(&mut chars, text.len(), |c| c.is_ascii_digit());
+            }
+            let number = &text[start..end];
+            if float {
+                Token::Float(number.parse().map_err(|_| error(column(start), "invalid number"))?)
+            } else if let Ok(v) = number.parse() {
+                Token::Int(v)
+            } else {
+                Token::UInt(number.parse().map_err(|_| error(column(start), "integer out of range"))?)
+            }
+        } else if matches!(c, '\'' | '"' | '`') {
+            chars.next();
+            let end = take_while(&mut chars, text.len(), |ch| ch != c);
+            if chars.next().is_none() {
+                return Err(error(column(start), "unterminated quote"))
+            }
+            let body = &text[start + 1..end];
+            if c == '\'' { Token::Str(body) } else { Token::Quoted(body) }
+        } else {
+            let rest = &text[start..];
+            let Some(&symbol) = SYMBOLS.iter().find(|&&s| rest.starts_with(s)) else {
+                return Err(error(column(start), format!("unexpected character '{c}'")))
+            };
+            symbol.chars().for_each(|_| { chars.next(); });
+            Token::Symbol(symbol)
+        };
+
+        tokens.push((column(start), token));
+    }
+    tokens.push((column(text.len()), Token::End));
+
+    Ok(tokens)
+}
+
+fn take_while(chars: &mut Peekable<CharIndices>, len: usize, f: impl Fn(char) -> bool) -> usize
+{
+    while chars.next_if(|&(_, c)| f(c)).is_some() {}
+    chars.peek().map_or(len, |&(i, _)| i)
+}
+
+type Binary<'a> = fn(Box<[Expression<'a>;2]>) -> Expression<'a>;
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token<'a>)>,
+    pos: usize,
+}
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Token<'a>
+    {
+        self.tokens[self.pos].1
+    }
+
+    fn column(&self) -> usize
+    {
+        self.tokens[self.pos].0
+    }
+
+    fn advance(&mut self)
+    {
+        if !matches!(self.peek(), Token::End) {
+            self.pos += 1
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError
+    {
+        error(self.column(), format!("expected {expected}, found {}", self.peek()))
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool
+    {
+        matches!(self.peek(), Token::Ident(s) if s.eq_ignore_ascii_case(keyword))
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool
+    {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.advance()
+        }
+        found
+    }
+
+    fn symbol(&mut self, symbol: &str) -> bool
+    {
+        let found = matches!(self.peek(), Token::Symbol(s) if s == symbol);
+        if found {
+            self.advance()
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()>
+    {
+        if self.keyword(keyword) { Ok(()) } else { Err(self.unexpected(&format!("'{keyword}'"))) }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<()>
+    {
+        if self.symbol(symbol) { Ok(()) } else { Err(self.unexpected(&format!("'{symbol}'"))) }
+    }
+
+    fn or(&mut self) -> Result<Expression<'a>>
+    {
+        let mut exprs = vec![self.and()?];
+        while self.keyword("or") {
+            exprs.push(self.and()?)
+        }
+
+        Ok(if exprs.len() == 1 { exprs.remove(0) } else { Expression::or(exprs) })
+    }
+
+    fn and(&mut self) -> Result<Expression<'a>>
+    {
+        let mut exprs = vec![self.not()?];
+        while self.keyword("and") {
+            exprs.push(self.not()?)
+        }
+
+        Ok(if exprs.len() == 1 { exprs.remove(0) } else { Expression::and(exprs) })
+    }
+
+    fn not(&mut self) -> Result<Expression<'a>>
+    {
+        if self.keyword("not") {
+            Ok(Expression::not(Box::new(self.not()?)))
+        } else {
+            self.comparison()
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expression<'a>>
+    {
+        use Expression as E;
+
+        let left = self.additive()?;
+        let op: Option<Binary> = match self.peek() {
+            Token::Symbol("=" | "==") => Some(E::eq),
+            Token::Symbol("!=" | "<>") => Some(E::ne),
+            Token::Symbol("<")  => Some(E::lt),
+            Token::Symbol("<=") => Some(E::lte),
+            Token::Symbol(">")  => Some(E::gt),
+            Token::Symbol(">=") => Some(E::gte),
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.advance();
+            return Ok(op(Box::new([left, self.additive()?])))
+        }
+
+        if self.keyword("is") {
+            let negated = self.keyword("not");
+            let left = Box::new(left);
+            let expr = if self.keyword("null") {
+                return Ok(if negated { E::is_not_null(left) } else { E::is_null(left) })
+            } else if self.keyword("true") {
+                E::is_true(left)
+            } else if self.keyword("false") {
+                E::is_false(left)
+            } else {
+                return Err(self.unexpected("null, true or false"))
+            };
+            return Ok(if negated { E::not(Box::new(expr)) } else { expr })
+        }
+
+        let negated = self.keyword("not");
+        if self.keyword("like") {
+            let op: Binary = if negated { E::not_like } else { E::like };
+            Ok(op(Box::new([left, self.additive()?])))
+        } else if self.keyword("ilike") {
+            let op: Binary = if negated { E::not_ilike } else { E::ilike };
+            Ok(op(Box::new([left, self.additive()?])))
+        } else if self.keyword("in") {
+            self.expect_symbol("(")?;
+            let list = self.list()?;
+            Ok(if negated { E::not_in(Box::new(left), list) } else { E::in_list(Box::new(left), list) })
+        } else if self.keyword("between") {
+            let low = self.additive()?;
+            self.expect_keyword("and")?;
+            let high = self.additive()?;
+            let exprs = Box::new([left, low, high]);
+            Ok(if negated { E::not_between(exprs) } else { E::between(exprs) })
+        } else if negated {
+            Err(self.unexpected("like, ilike, in or between"))
+        } else {
+            Ok(left)
+        }
+    }
+
+    fn additive(&mut self) -> Result<Expression<'a>>
+    {
+        let mut left = self.multiplicative()?;
+        loop {
+            let op: Binary = match self.peek() {
+                Token::Symbol("+") => Expression::add,
+                Token::Symbol("-") => Expression::sub,
+                _ => return Ok(left),
+            };
+            self.advance();
+            left = op(Box::new([left, self.multiplicative()?]));
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Expression<'a>>
+    {
+        let mut left = self.unary()?;
+        loop {
+            let op: Binary = match self.peek() {
+                Token::Symbol("*") => Expression::mul,
+                Token::Symbol("/") => Expression::div,
+                Token::Symbol("%") => Expression::modulus,
+                _ => return Ok(left),
+            };
+            self.advance();
+            left = op(Box::new([left, self.unary()?]));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression<'a>>
+    {
+        if self.symbol("-") {
+            Ok(match self.unary()? {
+                Expression::i64(v) => Expression::i64(-v),
+                Expression::f64(v) => Expression::f64(-v),
+                expr => Expression::sub(Box::new([Expression::i64(0), expr])),
+            })
+        } else {
+            self.postfix()
+        }
+    }
+
+    // Subscripts: items['name'] reads a struct field, items[1] a list element.
+    fn postfix(&mut self) -> Result<Expression<'a>>
+    {
+        let mut expr = self.primary()?;
+        while self.symbol("[") {
+            let index = self.or()?;
+            self.expect_symbol("]")?;
+            expr = match index {
+                Expression::str(name) => Expression::field(Box::new(expr), name),
+                index => Expression::list_index(Box::new([expr, index])),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expression<'a>>
+    {
+        let column = self.column();
+        let token = self.peek();
+        match token {
+            Token::Int(v) => { self.advance(); Ok(Expression::i64(v)) },
+            Token::UInt(v) => { self.advance(); Ok(Expression::u64(v)) },
+            Token::Float(v) => { self.advance(); Ok(Expression::f64(v)) },
+            Token::Str(s) => { self.advance(); Ok(Expression::str(s)) },
+            Token::Quoted(name) => { self.advance(); self.column_ref(name) },
+            Token::Symbol("(") => {
+                self.advance();
+                let expr = self.or()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            },
+            Token::Ident(word) if !RESERVED.iter().any(|r| word.eq_ignore_ascii_case(r)) => {
+                self.advance();
+                let lower = word.to_ascii_lowercase();
+                match (lower.as_str(), self.peek()) {
+                    ("case", _) => self.case(),
+                    ("true", _)  => Ok(Expression::bool(true)),
+                    ("false", _) => Ok(Expression::bool(false)),
+                    ("null", _)  => Ok(Expression::null(())),
+                    ("decimal", Token::Str(s)) => { self.advance(); Ok(Expression::decimal(s)) },
+                    ("date", Token::Str(s)) => { self.advance(); Ok(Expression::date(s)) },
+                    ("timestamp", Token::Str(s)) => { self.advance(); Ok(Expression::timestamp(s)) },
+                    ("interval", Token::Str(s)) => { self.advance(); Ok(Expression::interval(s)) },
+                    (_, Token::Symbol("(")) => { self.advance(); self.call(&lower, column) },
+                    _ => self.column_ref(word),
+                }
+            },
+            _ => Err(self.unexpected("an expression")),
+        }
+    }
+
+    fn column_ref(&mut self, name: &'a str) -> Result<Expression<'a>>
+    {
+        if !self.symbol(".") {
+            return Ok(Expression::col(name))
+        }
+
+        match self.peek() {
+            Token::Ident(field) | Token::Quoted(field) => {
+                self.advance();
+                Ok(Expression::qcol(name, field))
+            },
+            _ => Err(self.unexpected("a column name")),
+        }
+    }
+
+    fn case(&mut self) -> Result<Expression<'a>>
+    {
+        let expr = if self.is_keyword("when") { None } else { Some(Box::new(self.or()?)) };
+
+        let mut when = vec![];
+        while self.keyword("when") {
+            let condition = self.or()?;
+            self.expect_keyword("then")?;
+            when.push(WhenThen { when: condition, then: self.or()? });
+        }
+        if when.is_empty() {
+            return Err(self.unexpected("'when'"))
+        }
+
+        let otherwise = if self.keyword("else") { Some(Box::new(self.or()?)) } else { None };
+        self.expect_keyword("end")?;
+
+        Ok(Expression::case { expr, when, otherwise })
+    }
+
+    fn list(&mut self) -> Result<Vec<Expression<'a>>>
+    {
+        let mut items = vec![];
+        if self.symbol(")") {
+            return Ok(items)
+        }
+        loop {
+            items.push(self.or()?);
+            if self.symbol(")") {
+                return Ok(items)
+            }
+            if !self.symbol(",") {
+                return Err(self.unexpected("',' or ')'"))
+            }
+        }
+    }
+
+    // Collects the tokens of a type, such as decimal(12, 2) or list<utf8>,
+    // up to the closing parenthesis of the cast.
+    fn data_type(&mut self) -> Result<SchemaDataType>
+    {
+        let column = self.column();
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            let token = self.peek();
+            match token {
+                Token::End => break,
+                Token::Symbol(")") if depth == 0 => break,
+                Token::Symbol("(" | "<") => depth += 1,
+                Token::Symbol(")" | ">") => depth -= 1,
+                _ => {},
+            }
+            match token {
+                Token::Ident(s) | Token::Quoted(s) | Token::Symbol(s) => text.push_str(s),
+                Token::Str(s)   => text.push_str(&format!("'{s}'")),
+                Token::Int(v)   => text.push_str(&v.to_string()),
+                Token::UInt(v)  => text.push_str(&v.to_string()),
+                Token::Float(v) => text.push_str(&v.to_string()),
+                Token::End => {},
+            }
+            self.advance();
+        }
+
+        text.parse().map_err(|e| error(column, e))
+    }
+
+    fn call(&mut self, name: &str, column: usize) -> Result<Expression<'a>>
+    {
+        if name == "cast" {
+            let expr = self.or()?;
+            self.expect_keyword("as")?;
+            let dtype = self.data_type()?;
+            self.expect_symbol(")")?;
+            return Ok(Expression::cast(Box::new(expr), dtype))
+        }
+
+        let args = self.list()?;
+        let expr = function(name, column, args)?;
+        if self.keyword("filter") {
+            self.expect_symbol("(")?;
+            self.expect_keyword("where")?;
+            let predicate = self.or()?;
+            self.expect_symbol(")")?;
+            return Ok(Expression::filter(Box::new(expr), Box::new(predicate)))
+        }
+
+        Ok(expr)
+    }
+}
+
+fn function<'a>(name: &str, column: usize, args: Vec<Expression<'a>>) -> Result<Expression<'a>>
+{
+    use Expression as E;
+
+    let one = |args| arity::<1>(name, column, args).map(|[a]| Box::new(a));
+    let two = |args| arity::<2>(name, column, args).map(Box::new);
+    let three = |args| arity::<3>(name, column, args).map(Box::new);
+    let text = |expr| match expr {
+        E::str(s) => Ok(s),
+        _ => Err(error(column, format!("{name} expects a string literal"))),
+    };
+
+    let expr = match name {
+        "abs"     => E::abs(one(args)?),
+        "acos"    => E::acos(one(args)?),
+        "asin"    => E::asin(one(args)?),
+        "atan"    => E::atan(one(args)?),
+        "atan2"   => E::atan2(two(args)?),
+        "sin"     => E::sin(one(args)?),
+        "cos"     => E::cos(one(args)?),
+        "tan"     => E::tan(one(args)?),
+        "cot"     => E::cot(one(args)?),
+        "degrees" => E::degrees(one(args)?),
+        "radians" => E::radians(one(args)?),
+        "sqrt"    => E::sqrt(one(args)?),
+        "exp"     => E::exp(one(args)?),
+        "ln"      => E::ln(one(args)?),
+        "log10"   => E::log10(one(args)?),
+        "log"     => E::log(two(args)?),
+        "pow"     => E::pow(two(args)?),
+        "round"   => E::round(args),
+        "trunc"   => E::trunc(args),
+        "floor"   => E::floor(one(args)?),
+        "ceil"    => E::ceil(one(args)?),
+        "sign"    => E::sign(one(args)?),
+
+        "avg"    => E::avg(one(args)?),
+        "min"    => E::min(one(args)?),
+        "max"    => E::max(one(args)?),
+        "sum"    => E::sum(one(args)?),
+        "stddev" => E::stddev(one(args)?),
+        "var"    => E::var(one(args)?),
+        "count"  => E::count(one(args)?),
+        "median" => E::median(one(args)?),
+        "count_distinct" => E::count_distinct(one(args)?),
+        // Approximate, see Expression::percentile.
+        "percentile" => {
+            let [expr, p] = arity::<2>(name, column, args)?;
+            match p {
+                E::f64(p) => E::percentile(Box::new(expr), p),
+                E::i64(p) => E::percentile(Box::new(expr), p as f64),
+                _ => return Err(error(column, "percentile expects a numeric literal")),
+            }
+        },
+        "first" | "last" => {
+            let mut args = args.into_iter();
+            let Some(expr) = args.next() else {
+                return Err(error(column, format!("{name} takes at least 1 argument")))
+            };
+            let order = args.collect();
+            if name == "first" { E::first(Box::new(expr), order) } else { E::last(Box::new(expr), order) }
+        },
+        "string_agg" => {
+            let [expr, delimiter] = arity::<2>(name, column, args)?;
+            E::string_agg(Box::new(expr), text(delimiter)?)
+        },
+
+        "upper"  => E::upper(one(args)?),
+        "lower"  => E::lower(one(args)?),
+        "length" => E::length(one(args)?),
+        "trim"   => E::trim(args),
+        "ltrim"  => E::ltrim(args),
+        "rtrim"  => E::rtrim(args),
+        "concat" => E::concat(args),
+        "substr" => E::substr(args),
+        "lpad"   => E::lpad(args),
+        "rpad"   => E::rpad(args),
+        "replace"     => E::replace(three(args)?),
+        "split_part"  => E::split_part(three(args)?),
+        "starts_with" => E::starts_with(two(args)?),
+        "ends_with"   => E::ends_with(two(args)?),
+        "regexp_like"    => E::regexp_like(args),
+        "regexp_match"   => E::regexp_match(args),
+        "regexp_replace" => E::regexp_replace(args),
+
+        "coalesce" => E::coalesce(args),
+        "nullif"   => E::nullif(two(args)?),
+
+        "now" => { arity::<0>(name, column, args)?; E::now(()) },
+        "to_date"      => E::to_date(args),
+        "to_timestamp" => E::to_timestamp(args),
+        "format"       => E::format(two(args)?),
+        "local_time"   => E::local_time(one(args)?),
+        "date_trunc" | "date_part" => {
+            let [part, expr] = arity::<2>(name, column, args)?;
+            let (part, expr) = (text(part)?, Box::new(expr));
+            if name == "date_trunc" { E::date_trunc(part, expr) } else { E::date_part(part, expr) }
+        },
+        "date_diff" => {
+            let [unit, start, end] = arity::<3>(name, column, args)?;
+            E::date_diff(text(unit)?, Box::new([start, end]))
+        },
+        "at_timezone" => {
+            let [expr, tz] = arity::<2>(name, column, args)?;
+            E::at_timezone(Box::new(expr), text(tz)?)
+        },
+
+        "field" => {
+            let [expr, field] = arity::<2>(name, column, args)?;
+            E::field(Box::new(expr), text(field)?)
+        },
+        "list_index"  => E::list_index(two(args)?),
+        "list_length" => E::list_length(one(args)?),
+        "json_extract" => {
+            let [expr, path] = arity::<2>(name, column, args)?;
+            E::json_extract(Box::new(expr), text(path)?)
+        },
+        "call" => {
+            let mut args = args.into_iter();
+            let Some(udf) = args.next() else {
+                return Err(error(column, "call takes a udf name and its arguments"))
+            };
+            E::call(text(udf)?, args.collect())
+        },
+        "apply" => {
+            let mut args = args.into_iter();
+            let Some(function) = args.next() else {
+                return Err(error(column, "apply takes a function name and its arguments"))
+            };
+            E::apply(text(function)?, args.collect())
+        },
+        "json_parse" => {
+            let [expr, dtype] = arity::<2>(name, column, args)?;
+            let dtype = text(dtype)?.parse().map_err(|e| error(column, e))?;
+            E::json_parse(Box::new(expr), dtype)
+        },
+
+        _ => return Err(error(column, format!("unknown function '{name}'"))),
+    };
+
+    Ok(expr)
+}
+
+fn arity<'a, const N: usize>(
+    name: &str,
+    column: usize,
+    args: Vec<Expression<'a>>
+) -> Result<[Expression<'a>; N]>
+{
+    let found = args.len();
+    args.try_into()
+        .map_err(|_| error(column, format!("{name} takes {N} argument(s), found {found}")))
+}
@@ -1,8 +1,10 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
-use datafusion::arrow::datatypes::{DataType, TimeUnit};
+use std::str::FromStr;
+use std::sync::Arc;
+use datafusion::arrow::datatypes::{DataType, Field, Fields, TimeUnit, DECIMAL128_MAX_PRECISION};
 use datafusion::parquet::basic::Compression;
-use serde::Deserialize;
+use serde::{de, Deserialize, Deserializer};
 
 use crate::expr::Expression;
 
@@ -37,29 +39,68 @@ impl Into<Compression> for ParquetCompression {
     }
 }
 
-#[derive(Clone, Copy, Debug, Default, Deserialize)]
-#[allow(non_camel_case_types)]
-pub enum InputSide {
-    #[default]
-    left,
-    right,
-}
-
 #[derive(Debug, Deserialize)]
 pub struct Plan<'a> {
     pub id: &'a str,
     pub name: &'a str,
     pub links: Vec<Link<'a>>,
     pub tools: Vec<Tool<'a>>,
+
+    #[serde(borrow, default)]
+    pub udfs: Vec<Udf<'a>>,
+
+    #[serde(borrow, default)]
+    pub functions: Vec<Function<'a>>,
+}
+
+// A named expression template. Within the body a parameter is referenced
+// as a column of the same name and replaced by the argument when applied.
+#[derive(Debug, Deserialize)]
+pub struct Function<'a> {
+    pub name: &'a str,
+    pub params: Vec<&'a str>,
+
+    #[serde(borrow)]
+    pub body: Expression<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Udf<'a> {
+    pub name: &'a str,
+    pub module: &'a str,
+    pub export: Option<&'a str>,
+    pub args: Vec<WasmType>,
+    pub returns: WasmType,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum WasmType {
+    i32,
+    i64,
+    f32,
+    f64,
+}
+#[allow(clippy::from_over_into)]
+impl Into<DataType> for WasmType {
+    fn into(self) -> DataType
+    {
+        match self {
+            WasmType::i32 => DataType::Int32,
+            WasmType::i64 => DataType::Int64,
+            WasmType::f32 => DataType::Float32,
+            WasmType::f64 => DataType::Float64,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Link<'a> {
     pub src: &'a str,
     pub dst: &'a str,
-
-    #[serde(default)]
-    pub input: InputSide,
+    pub input: Option<&'a str>,
+    pub output: Option<&'a str>,
+    pub table: Option<&'a str>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -69,16 +110,27 @@ pub enum Tool<'a> {
     #[serde(borrow)]
     import(Import<'a>),
     export(Export<'a>),
+    columns(Columns<'a>),
+    dedupe(Dedupe<'a>),
+    diff(Diff<'a>),
     distinct(Generic<'a>),
     difference(Generic<'a>),
     filter(Filter<'a>),
+    formula(Formula<'a>),
+    fuzzy_match(FuzzyMatch<'a>),
     intersect(Generic<'a>),
     join(Join<'a>),
+    json_parse(JsonParse<'a>),
     map(Map<'a>),
+    profile(Profile<'a>),
+    scd2(Scd2<'a>),
     select(Select<'a>),
     sort(Sort<'a>),
+    sql(Query<'a>),
     summarize(Summarize<'a>),
     union(Union<'a>),
+    unnest(Unnest<'a>),
+    validate(Validate<'a>),
 }
 impl<'a> Tool<'a> {
     pub fn id(&self) -> &'a str
@@ -86,18 +138,29 @@ impl<'a> Tool<'a> {
         use Tool::*;
 
         match self {
-            import(tool)     => tool.id(),
-            export(tool)     => tool.id(),
-            distinct(tool)   => tool.id,
-            difference(tool) => tool.id,
-            filter(tool)     => tool.id,
-            intersect(tool)  => tool.id,
-            join(tool)       => tool.id,
-            map(tool)        => tool.id,
-            select(tool)     => tool.id,
-            sort(tool)       => tool.id,
-            summarize(tool)  => tool.id,
-            union(tool)      => tool.id,
+            import(tool)      => tool.id(),
+            export(tool)      => tool.id(),
+            columns(tool)     => tool.id,
+            dedupe(tool)      => tool.id,
+            diff(tool)        => tool.id,
+            distinct(tool)    => tool.id,
+            difference(tool)  => tool.id,
+            filter(tool)      => tool.id,
+            formula(tool)     => tool.id,
+            fuzzy_match(tool) => tool.id,
+            intersect(tool)   => tool.id,
+            join(tool)        => tool.id,
+            json_parse(tool)  => tool.id,
+            map(tool)         => tool.id,
+            profile(tool)     => tool.id,
+            scd2(tool)        => tool.id,
+            select(tool)      => tool.id,
+            sort(tool)        => tool.id,
+            sql(tool)         => tool.id,
+            summarize(tool)   => tool.id,
+            union(tool)       => tool.id,
+            unnest(tool)      => tool.id,
+            validate(tool)    => tool.id,
         }
     }
 }
@@ -122,13 +185,19 @@ impl<'a> Import<'a> {
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+#[derive(Clone, Debug)]
 #[allow(non_camel_case_types)]
 pub enum SchemaDataType {
-    utf8, bool, null, ts, ms, ns,
+    utf8, large_utf8, binary, large_binary, bool, null,
+    ts, ms, us, ns,
     i8, i16, i32, i64,
     u8, u16, u32, u64,
     f16, f32, f64, date,
+    decimal(u8, i8),
+    tz(TimeUnit, String),
+    list(Box<SchemaDataType>),
+    map(Box<SchemaDataType>, Box<SchemaDataType>),
+    structure(Vec<(String, SchemaDataType)>),
 }
 #[allow(clippy::from_over_into)]
 impl Into<DataType> for SchemaDataType {
@@ -137,6 +206,7 @@ impl Into<DataType> for SchemaDataType {
         match self {
             SchemaDataType::ts   => DataType::Timestamp(TimeUnit::Second, None),
             SchemaDataType::ms   => DataType::Timestamp(TimeUnit::Millisecond, None),
+            SchemaDataType::us   => DataType::Timestamp(TimeUnit::Microsecond, None),
             SchemaDataType::ns   => DataType::Timestamp(TimeUnit::Nanosecond, None),
             SchemaDataType::utf8 => DataType::Utf8,
             SchemaDataType::bool => DataType::Boolean,
@@ -153,10 +223,168 @@ impl Into<DataType> for SchemaDataType {
             SchemaDataType::f32  => DataType::Float32,
             SchemaDataType::f64  => DataType::Float64,
             SchemaDataType::date => DataType::Date32,
+            SchemaDataType::binary       => DataType::Binary,
+            SchemaDataType::large_utf8   => DataType::LargeUtf8,
+            SchemaDataType::large_binary => DataType::LargeBinary,
+            SchemaDataType::decimal(p, s) if p > DECIMAL128_MAX_PRECISION =>
+                DataType::Decimal256(p, s),
+            SchemaDataType::decimal(p, s) => DataType::Decimal128(p, s),
+            SchemaDataType::tz(unit, tz)  => DataType::Timestamp(unit, Some(tz.into())),
+            SchemaDataType::list(dtype)   => DataType::new_list((*dtype).into(), true),
+            SchemaDataType::map(key, value) => {
+                let entries = Fields::from(vec![
+                    Field::new("key", (*key).into(), false),
+                    Field::new("value", (*value).into(), true),
+                ]);
+                let entries = Field::new("entries", DataType::Struct(entries), false);
+                DataType::Map(Arc::new(entries), false)
+            },
+            SchemaDataType::structure(fields) => DataType::Struct(fields.into_iter()
+                .map(|(name, dtype)| Field::new(name, dtype.into(), true))
+                .collect()),
+        }
+    }
+}
+impl<'de> Deserialize<'de> for SchemaDataType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>
+    {
+        let text = Cow::<str>::deserialize(deserializer)?;
+        text.parse().map_err(de::Error::custom)
+    }
+}
+impl FromStr for SchemaDataType {
+    type Err = String;
+
+    // Type names are either simple, "i64", or parameterized such as
+    // "decimal(12,2)", "ms(UTC)", "list<utf8>", "map<utf8,i64>" and
+    // "struct<name:utf8,tags:list<utf8>>".
+    fn from_str(text: &str) -> Result<Self, Self::Err>
+    {
+        let mut rest = text;
+        let dtype = parse_type(&mut rest).map_err(|e| format!("{e} in type '{text}'"))?;
+        if !rest.trim().is_empty() {
+            return Err(format!("unexpected '{}' in type '{text}'", rest.trim()))
         }
+
+        Ok(dtype)
     }
 }
 
+fn parse_type(s: &mut &str) -> Result<SchemaDataType, String>
+{
+    use SchemaDataType as T;
+
+    fn name<'a>(s: &mut &'a str) -> &'a str
+    {
+        *s = s.trim_start();
+        let end = s.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(s.len());
+        let (name, rest) = s.split_at(end);
+        *s = rest;
+        name
+    }
+    fn eat(s: &mut &str, c: char) -> bool
+    {
+        *s = s.trim_start();
+        let found = s.starts_with(c);
+        if found {
+            *s = &s[1..];
+        }
+        found
+    }
+    fn expect(s: &mut &str, c: char) -> Result<(), String>
+    {
+        if eat(s, c) { Ok(()) } else { Err(format!("expected '{c}'")) }
+    }
+    fn number<T: FromStr>(s: &mut &str) -> Result<T, String>
+    {
+        let negative = eat(s, '-');
+        let digits = name(s);
+        let digits = if negative { format!("-{digits}") } else { digits.into() };
+        digits.parse().map_err(|_| format!("invalid number '{digits}'"))
+    }
+
+    let dtype = match name(s) {
+        "utf8" => T::utf8,
+        "large_utf8" => T::large_utf8,
+        "binary" => T::binary,
+        "large_binary" => T::large_binary,
+        "bool" => T::bool,
+        "null" => T::null,
+        "i8"  => T::i8,
+        "i16" => T::i16,
+        "i32" => T::i32,
+        "i64" => T::i64,
+        "u8"  => T::u8,
+        "u16" => T::u16,
+        "u32" => T::u32,
+        "u64" => T::u64,
+        "f16" => T::f16,
+        "f32" => T::f32,
+        "f64" => T::f64,
+        "date" => T::date,
+        unit @ ("ts" | "ms" | "us" | "ns") => {
+            let unit = match unit {
+                "ts" => TimeUnit::Second,
+                "ms" => TimeUnit::Millisecond,
+                "us" => TimeUnit::Microsecond,
+                _    => TimeUnit::Nanosecond,
+            };
+            if eat(s, '(') {
+                let end = s.find(')').ok_or("expected ')'")?;
+                let zone = s[..end].trim().trim_matches('\'').to_string();
+                *s = &s[end + 1..];
+                T::tz(unit, zone)
+            } else {
+                match unit {
+                    TimeUnit::Second      => T::ts,
+                    TimeUnit::Millisecond => T::ms,
+                    TimeUnit::Microsecond => T::us,
+                    TimeUnit::Nanosecond  => T::ns,
+                }
+            }
+        },
+        "decimal" => {
+            expect(s, '(')?;
+            let precision = number(s)?;
+            let scale = if eat(s, ',') { number(s)? } else { 0 };
+            expect(s, ')')?;
+            T::decimal(precision, scale)
+        },
+        "list" => {
+            expect(s, '<')?;
+            let dtype = parse_type(s)?;
+            expect(s, '>')?;
+            T::list(Box::new(dtype))
+        },
+        "map" => {
+            expect(s, '<')?;
+            let key = parse_type(s)?;
+            expect(s, ',')?;
+            let value = parse_type(s)?;
+            expect(s, '>')?;
+            T::map(Box::new(key), Box::new(value))
+        },
+        "struct" => {
+            expect(s, '<')?;
+            let mut fields = vec![];
+            loop {
+                let field = name(s).to_string();
+                expect(s, ':')?;
+                fields.push((field, parse_type(s)?));
+                if !eat(s, ',') {
+                    break
+                }
+            }
+            expect(s, '>')?;
+            T::structure(fields)
+        },
+        "" => return Err("expected a type name".into()),
+        other => return Err(format!("unknown type '{other}'")),
+    };
+
+    Ok(dtype)
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SchemaField<'a> {
     pub column: &'a str,
@@ -239,6 +467,82 @@ pub struct ExportParquet<'a> {
     pub path: &'a str,
     pub compress: Option<ParquetCompression>,
     pub overwrite: Option<bool>,
+    pub merge: Option<MergeParquet<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeParquet<'a> {
+    #[serde(borrow)]
+    pub keys: Vec<&'a str>,
+    pub delete_unmatched: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Columns<'a> {
+    pub id: &'a str,
+    pub select: Option<Vec<ColumnMatch<'a>>>,
+    pub deselect: Option<Vec<ColumnMatch<'a>>>,
+    pub rename: Option<HashMap<&'a str, &'a str>>,
+    pub replace: Option<Vec<ColumnReplace<'a>>>,
+    pub normalize: Option<Vec<NameCase>>,
+    pub order: Option<Vec<ColumnOrder<'a>>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum ColumnMatch<'a> {
+    name(&'a str),
+    regex(&'a str),
+
+    #[serde(rename(deserialize = "type"))]
+    dtype(SchemaDataType),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ColumnReplace<'a> {
+    pub pattern: &'a str,
+    pub with: &'a str,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum NameCase {
+    trim,
+    lowercase,
+    uppercase,
+    snake_case,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum ColumnOrder<'a> {
+    col(&'a str),
+    rest,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Dedupe<'a> {
+    pub id: &'a str,
+    pub keys: Vec<&'a str>,
+    pub keep: Option<Keep>,
+
+    #[serde(borrow, default)]
+    pub order: Vec<SortExpression<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Diff<'a> {
+    pub id: &'a str,
+    pub keys: Vec<&'a str>,
+    pub columns: Option<Vec<&'a str>>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum Keep {
+    #[default]
+    first,
+    last,
 }
 
 #[derive(Debug, Deserialize)]
@@ -252,22 +556,134 @@ pub struct Filter<'a> {
     pub expr: Expression<'a>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Formula<'a> {
+    pub id: &'a str,
+    pub exprs: Vec<FormulaField<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FormulaField<'a> {
+    pub name: &'a str,
+
+    #[serde(borrow)]
+    pub expr: Expression<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FuzzyMatch<'a> {
+    pub id: &'a str,
+    pub key: &'a str,
+    pub fields: Vec<FuzzyField<'a>>,
+    pub block: Option<Vec<&'a str>>,
+    pub threshold: f64,
+    pub ignore_case: Option<bool>,
+    pub cluster: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FuzzyField<'a> {
+    pub column: &'a str,
+    pub algorithm: Similarity,
+    pub weight: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum Similarity {
+    levenshtein,
+    jaro_winkler,
+    token_set,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Join<'a> {
     pub id: &'a str,
+
+    #[serde(default)]
     pub lt: Vec<&'a str>,
+
+    #[serde(default)]
     pub rt: Vec<&'a str>,
 
+    #[serde(borrow)]
+    pub on: Option<Expression<'a>>,
+    pub cross: Option<bool>,
+    pub null_equal: Option<bool>,
+    pub prefixes: Option<(&'a str, &'a str)>,
+    pub suffixes: Option<(&'a str, &'a str)>,
+    pub asof: Option<AsOfJoin<'a>>,
+    pub range: Option<RangeJoin<'a>>,
+
     #[serde(rename(deserialize = "type"))]
     pub variant: JoinType,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct AsOfJoin<'a> {
+    pub left: &'a str,
+    pub right: &'a str,
+    pub direction: Option<AsOfDirection>,
+    pub tolerance: Option<&'a str>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum AsOfDirection {
+    #[default]
+    backward,
+    forward,
+    nearest,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RangeJoin<'a> {
+    pub col: &'a str,
+    pub start: &'a str,
+    pub end: &'a str,
+    pub inclusive: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JsonParse<'a> {
+    pub id: &'a str,
+    pub column: &'a str,
+    pub schema: SchemaDataType,
+    pub flatten: Option<bool>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Map<'a> {
     pub id: &'a str,
     pub exprs: Vec<Expression<'a>>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Profile<'a> {
+    pub id: &'a str,
+    pub approximate: Option<bool>,
+    pub top_k: Option<usize>,
+    pub bins: Option<usize>,
+    pub report: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Query<'a> {
+    pub id: &'a str,
+    pub stmt: Cow<'a, str>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Scd2<'a> {
+    pub id: &'a str,
+    pub keys: Vec<&'a str>,
+    pub tracked: Vec<&'a str>,
+    pub close_missing: Option<bool>,
+
+    #[serde(borrow)]
+    pub effective: Option<Expression<'a>>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Select<'a> {
     pub id: &'a str,
@@ -292,6 +708,68 @@ pub struct Summarize<'a> {
 pub struct Union<'a> {
     pub id: &'a str,
     pub distinct: Option<bool>,
+    pub by_name: Option<bool>,
+    pub allow_utf8: Option<bool>,
+    pub source: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Unnest<'a> {
+    pub id: &'a str,
+    pub columns: Vec<&'a str>,
+    pub preserve_nulls: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Validate<'a> {
+    pub id: &'a str,
+
+    #[serde(borrow)]
+    pub rules: Vec<Rule<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Rule<'a> {
+    pub name: &'a str,
+    pub severity: Option<Severity>,
+
+    #[serde(borrow)]
+    pub check: Check<'a>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum Severity {
+    #[default]
+    fail,
+    warn,
+    reject,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum Check<'a> {
+    not_null(&'a str),
+    unique(Vec<&'a str>),
+    range {
+        column: &'a str,
+        min: Option<Expression<'a>>,
+        max: Option<Expression<'a>>,
+    },
+    regex {
+        column: &'a str,
+        pattern: &'a str,
+    },
+    allowed {
+        column: &'a str,
+        values: Vec<Expression<'a>>,
+    },
+    expr(Expression<'a>),
+    row_count {
+        min: Option<usize>,
+        max: Option<usize>,
+    },
+    schema(Vec<SchemaField<'a>>),
 }
 
 #[derive(Debug, Deserialize)]
@@ -314,4 +792,6 @@ pub enum JoinType {
     right_semi,
     left_anti,
     right_anti,
+    asof,
+    range,
 }
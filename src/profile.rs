@@ -0,0 +1,339 @@
+use std::sync::Arc;
+
+use datafusion::arrow::array::{ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray};
+use datafusion::arrow::datatypes::DataType;
+use datafusion::common::ScalarValue;
+use datafusion::error::Result;
+use datafusion::functions_aggregate::expr_fn::{
+    approx_distinct, avg, count, count_distinct, max, min, stddev
+};
+use datafusion::functions_window::expr_fn::row_number;
+use datafusion::logical_expr::ExprFunctionExt;
+use datafusion::prelude::{
+    cast, col, floor, get_field, ident, lit, make_array, named_struct, when, DataFrame
+};
+
+use crate::config::ProfileConfig;
+
+#[derive(Debug, Default)]
+pub struct ColumnProfile {
+    pub name: String,
+    pub data_type: String,
+    pub rows: i64,
+    pub nulls: i64,
+    pub distinct: Option<i64>,
+    pub min: Option<String>,
+    pub max: Option<String>,
+    pub mean: Option<f64>,
+    pub stddev: Option<f64>,
+    pub top: Vec<(String, i64)>,
+    pub histogram: Vec<(f64, f64, i64)>,
+}
+
+// The statistics take one pass over the input, then the top values of
+// every column and the histograms of every numeric column take one each.
+pub async fn profile(df: DataFrame, config: &ProfileConfig) -> Result<Vec<ColumnProfile>>
+{
+    let fields = df.schema().fields().iter()
+        .map(|field| (field.name().clone(), field.data_type().clone()))
+        .collect::<Vec<_>>();
+
+    let mut aggr = vec![count(lit(1)).alias("rows")];
+    for (i, (name, dtype)) in fields.iter().enumerate() {
+        let c = ident(name);
+        aggr.push(count(c.clone()).alias(format!("{i}_count")));
+        if is_orderable(dtype) {
+            let distinct = if config.approximate {
+                cast(approx_distinct(c.clone()), DataType::Int64)
+            } else {
+                count_distinct(c.clone())
+            };
+            aggr.push(distinct.alias(format!("{i}_distinct")));
+            aggr.push(min(c.clone()).alias(format!("{i}_min")));
+            aggr.push(max(c.clone()).alias(format!("{i}_max")));
+        }
+        if dtype.is_numeric() {
+            let f = cast(c, DataType::Float64);
+            aggr.push(avg(f.clone()).alias(format!("{i}_mean")));
+            aggr.push(stddev(f.clone()).alias(format!("{i}_stddev")));
+            aggr.push(min(f.clone()).alias(format!("{i}_lo")));
+            aggr.push(max(f).alias(format!("{i}_hi")));
+        }
+    }
+
+    let batches = df.clone().aggregate(vec![], aggr)?.collect().await?;
+    let stats = &batches[0];
+    let rows = int(stats, "rows")?.unwrap_or(0);
+
+    let mut profiles = vec![];
+    let mut ordered = vec![];
+    let mut ranges = vec![];
+    for (i, (name, dtype)) in fields.iter().enumerate() {
+        let mut profile = ColumnProfile {
+            rows,
+            name: name.clone(),
+            data_type: dtype.to_string(),
+            nulls: rows - int(stats, &format!("{i}_count"))?.unwrap_or(0),
+            ..Default::default()
+        };
+        if is_orderable(dtype) {
+            profile.distinct = int(stats, &format!("{i}_distinct"))?;
+            profile.min = text(stats, &format!("{i}_min"))?;
+            profile.max = text(stats, &format!("{i}_max"))?;
+            ordered.push((i, name.as_str()));
+        }
+        if dtype.is_numeric() {
+            profile.mean   = float(stats, &format!("{i}_mean"))?;
+            profile.stddev = float(stats, &format!("{i}_stddev"))?;
+            let lo = float(stats, &format!("{i}_lo"))?;
+            let hi = float(stats, &format!("{i}_hi"))?;
+            if let (Some(lo), Some(hi)) = (lo, hi) {
+                ranges.push((i, name.as_str(), lo, hi));
+            }
+        }
+        profiles.push(profile);
+    }
+
+    for (i, top) in top_values(&df, &ordered, config.top_k).await? {
+        profiles[i].top = top;
+    }
+    for (i, histogram) in histograms(&df, &ranges, config.bins).await? {
+        profiles[i].histogram = histogram;
+    }
+
+    Ok(profiles)
+}
+
+// Unpivots the columns into (column, value) cells so the values of every
+// column are counted and ranked together.
+async fn top_values(
+    df: &DataFrame,
+    columns: &[(usize, &str)],
+    k: usize
+) -> Result<Vec<(usize, Vec<(String, i64)>)>>
+{
+    if columns.is_empty() || k == 0 {
+        return Ok(vec![])
+    }
+
+    let cells = columns.iter()
+        .map(|&(i, name)| named_struct(vec![
+            lit("column"), lit(i as i64),
+            lit("value"), cast(ident(name), DataType::Utf8),
+        ]))
+        .collect();
+    let rank = row_number()
+        .partition_by(vec![col("column")])
+        .order_by(vec![col("n").sort(false, false), col("value").sort(true, false)])
+        .build()?;
+    let batches = df.clone()
+        .select(vec![make_array(cells).alias("cell")])?
+        .unnest_columns(&["cell"])?
+        .select(vec![
+            get_field(col("cell"), "column").alias("column"),
+            get_field(col("cell"), "value").alias("value"),
+        ])?
+        .filter(col("value").is_not_null())?
+        .aggregate(vec![col("column"), col("value")], vec![count(lit(1)).alias("n")])?
+        .with_column("rank", rank)?
+        .filter(col("rank").lt_eq(lit(k as u64)))?
+        .sort(vec![col("column").sort(true, false), col("rank").sort(true, false)])?
+        .collect()
+        .await?;
+
+    let mut top: Vec<(usize, Vec<(String, i64)>)> = vec![];
+    for batch in batches {
+        for r in 0..batch.num_rows() {
+            let column = ScalarValue::try_from_array(batch.column(0), r)?;
+            let value = ScalarValue::try_from_array(batch.column(1), r)?;
+            let n = ScalarValue::try_from_array(batch.column(2), r)?;
+            if let (ScalarValue::Int64(Some(i)), ScalarValue::Int64(Some(n))) = (column, n) {
+                let i = i as usize;
+                match top.last_mut() {
+                    Some((last, values)) if *last == i => values.push((value.to_string(), n)),
+                    _ => top.push((i, vec![(value.to_string(), n)])),
+                }
+            }
+        }
+    }
+
+    Ok(top)
+}
+
+// Counts every bin of every column as a filtered aggregate of one pass.
+async fn histograms(
+    df: &DataFrame,
+    ranges: &[(usize, &str, f64, f64)],
+    bins: usize
+) -> Result<Vec<(usize, Vec<(f64, f64, i64)>)>>
+{
+    let ranges = ranges.iter()
+        .filter(|(_, _, lo, hi)| hi > lo)
+        .collect::<Vec<_>>();
+    if ranges.is_empty() || bins == 0 {
+        return Ok(vec![])
+    }
+
+    let last = bins as i64 - 1;
+    let mut aggr = vec![];
+    for &&(i, name, lo, hi) in &ranges {
+        let width = (hi - lo) / bins as f64;
+        let bucket = cast(
+            floor((cast(ident(name), DataType::Float64) - lit(lo)) / lit(width)),
+            DataType::Int64
+        );
+        let bucket = when(bucket.clone().gt(lit(last)), lit(last)).otherwise(bucket)?;
+        for b in 0..bins {
+            let n = count(lit(1)).filter(bucket.clone().eq(lit(b as i64))).build()?;
+            aggr.push(n.alias(format!("{i}_bin{b}")));
+        }
+    }
+
+    let batches = df.clone().aggregate(vec![], aggr)?.collect().await?;
+    let counts = &batches[0];
+
+    ranges.into_iter()
+        .map(|&(i, _, lo, hi)| {
+            let width = (hi - lo) / bins as f64;
+            let histogram = (0..bins)
+                .map(|b| {
+                    let n = int(counts, &format!("{i}_bin{b}"))?.unwrap_or(0);
+                    Ok((lo + width * b as f64, lo + width * (b + 1) as f64, n))
+                })
+                .collect::<Result<_>>()?;
+            Ok((i, histogram))
+        })
+        .collect()
+}
+
+pub fn to_batch(profiles: &[ColumnProfile]) -> Result<RecordBatch>
+{
+    let strings = |f: &dyn Fn(&ColumnProfile) -> Option<String>|
+        Arc::new(profiles.iter().map(f).collect::<StringArray>()) as ArrayRef;
+    let ints = |f: &dyn Fn(&ColumnProfile) -> Option<i64>|
+        Arc::new(profiles.iter().map(f).collect::<Int64Array>()) as ArrayRef;
+    let floats = |f: &dyn Fn(&ColumnProfile) -> Option<f64>|
+        Arc::new(profiles.iter().map(f).collect::<Float64Array>()) as ArrayRef;
+
+    let batch = RecordBatch::try_from_iter(vec![
+        ("column", strings(&|p| Some(p.name.clone()))),
+        ("data_type", strings(&|p| Some(p.data_type.clone()))),
+        ("rows", ints(&|p| Some(p.rows))),
+        ("null_count", ints(&|p| Some(p.nulls))),
+        ("distinct_count", ints(&|p| p.distinct)),
+        ("min", strings(&|p| p.min.clone())),
+        ("max", strings(&|p| p.max.clone())),
+        ("mean", floats(&|p| p.mean)),
+        ("stddev", floats(&|p| p.stddev)),
+        ("top_values", strings(&|p| Some(p.top.iter()
+            .map(|(v, n)| format!("{v} ({n})"))
+            .collect::<Vec<_>>()
+            .join(", ")))),
+        ("histogram", strings(&|p| Some(p.histogram.iter()
+            .map(|(lo, hi, n)| format!("[{lo}, {hi}): {n}"))
+            .collect::<Vec<_>>()
+            .join("; ")))),
+    ])?;
+
+    Ok(batch)
+}
+
+pub fn to_html(profiles: &[ColumnProfile]) -> String
+{
+    let mut html = String::from(concat!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+        "<title>Profile</title>\n<style>\n",
+        "body { font-family: sans-serif; margin: 2em; }\n",
+        "table { border-collapse: collapse; margin-bottom: 2em; }\n",
+        "td, th { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }\n",
+        ".bar { background: #4a90d9; height: 12px; }\n",
+        "</style>\n</head>\n<body>\n",
+    ));
+
+    for p in profiles {
+        let opt = |v: &Option<String>| v.as_deref().map_or(String::new(), escape);
+        let num = |v: Option<f64>| v.map_or(String::new(), |v| format!("{v:.4}"));
+
+        html.push_str(&format!("<h2>{}</h2>\n<table>\n", escape(&p.name)));
+        html.push_str(&format!("<tr><th>type</th><td>{}</td></tr>\n", escape(&p.data_type)));
+        html.push_str(&format!("<tr><th>rows</th><td>{}</td></tr>\n", p.rows));
+        html.push_str(&format!("<tr><th>nulls</th><td>{}</td></tr>\n", p.nulls));
+        html.push_str(&format!(
+            "<tr><th>distinct</th><td>{}</td></tr>\n",
+            p.distinct.map_or(String::new(), |v| v.to_string())
+        ));
+        html.push_str(&format!("<tr><th>min</th><td>{}</td></tr>\n", opt(&p.min)));
+        html.push_str(&format!("<tr><th>max</th><td>{}</td></tr>\n", opt(&p.max)));
+        html.push_str(&format!("<tr><th>mean</th><td>{}</td></tr>\n", num(p.mean)));
+        html.push_str(&format!("<tr><th>stddev</th><td>{}</td></tr>\n", num(p.stddev)));
+        html.push_str("</table>\n");
+
+        if !p.top.is_empty() {
+            html.push_str("<table>\n<tr><th>value</th><th>count</th></tr>\n");
+            for (value, n) in &p.top {
+                html.push_str(&format!("<tr><td>{}</td><td>{n}</td></tr>\n", escape(value)));
+            }
+            html.push_str("</table>\n");
+        }
+
+        let most = p.histogram.iter().map(|(_, _, n)| *n).max().unwrap_or(0);
+        if most > 0 {
+            html.push_str("<table>\n<tr><th>bin</th><th>count</th><th></th></tr>\n");
+            for (lo, hi, n) in &p.histogram {
+                let width = n * 300 / most;
+                html.push_str(&format!(
+                    "<tr><td>{lo:.2} - {hi:.2}</td><td>{n}</td>\
+                     <td><div class=\"bar\" style=\"width: {width}px\"></div></td></tr>\n"
+                ));
+            }
+            html.push_str("</table>\n");
+        }
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn is_orderable(dtype: &DataType) -> bool
+{
+    dtype.is_numeric() || dtype.is_temporal() || matches!(dtype,
+        DataType::Boolean | DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View
+    )
+}
+
+fn escape(s: &str) -> String
+{
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn scalar(batch: &RecordBatch, name: &str) -> Result<ScalarValue>
+{
+    ScalarValue::try_from_array(batch.column_by_name(name).unwrap(), 0)
+}
+
+fn int(batch: &RecordBatch, name: &str) -> Result<Option<i64>>
+{
+    match scalar(batch, name)? {
+        ScalarValue::Int64(v) => Ok(v),
+        v => match v.cast_to(&DataType::Int64)? {
+            ScalarValue::Int64(v) => Ok(v),
+            _ => Ok(None),
+        },
+    }
+}
+
+fn float(batch: &RecordBatch, name: &str) -> Result<Option<f64>>
+{
+    match scalar(batch, name)? {
+        ScalarValue::Float64(v) => Ok(v),
+        _ => Ok(None),
+    }
+}
+
+// Cast to text like the top values, as temporal scalars display as their
+// epoch integers.
+fn text(batch: &RecordBatch, name: &str) -> Result<Option<String>>
+{
+    let value = scalar(batch, name)?.cast_to(&DataType::Utf8)?;
+    Ok((!value.is_null()).then(|| value.to_string()))
+}
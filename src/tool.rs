@@ -1,36 +1,92 @@
+use std::collections::HashMap;
+
+use datafusion::common::plan_err;
 use datafusion::error::Result;
 use datafusion::execution::context::SessionContext;
-use datafusion::prelude::DataFrame;
 
 use crate::actions::*;
 use crate::config::*;
+use crate::macros::Macros;
 use crate::plans;
 
 pub use crate::actions::Data as ToolData;
+pub use crate::actions::{Order, Output};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Port {
+    pub name: &'static str,
+    pub variadic: bool,
+    pub aliases: &'static [&'static str],
+}
+impl Port {
+    const fn one(name: &'static str) -> Port
+    {
+        Port { name, variadic: false, aliases: &[] }
+    }
+
+    const fn many(name: &'static str) -> Port
+    {
+        Port { name, variadic: true, aliases: &[] }
+    }
+
+    // Other names a link may use for the port, ranked in the order given.
+    const fn or(self, aliases: &'static [&'static str]) -> Port
+    {
+        Port { aliases, ..self }
+    }
+}
+
+const NO_PORTS: &[Port]   = &[];
+const ONE_PORT: &[Port]   = &[Port::one(INPUT)];
+const TWO_PORTS: &[Port]  = &[Port::one(LEFT), Port::one(RIGHT)];
+const MANY_PORTS: &[Port] = &[Port::many(INPUT)];
+
+// Unions took a left and a right input before ports were variadic.
+const UNION_PORTS: &[Port] = &[Port::many(INPUT).or(&[LEFT, RIGHT])];
+
+const NO_OUTPUTS: &[&str]       = &[];
+const ONE_OUTPUT: &[&str]       = &[OUTPUT];
+const FILTER_OUTPUTS: &[&str]   = &[TRUE, FALSE];
+const JOIN_OUTPUTS: &[&str]     = &[JOINED, LEFT_UNMATCHED, RIGHT_UNMATCHED];
+const DEDUPE_OUTPUTS: &[&str]   = &[OUTPUT, DUPLICATES];
+const DIFF_OUTPUTS: &[&str]     = &[ADDED, REMOVED, CHANGED, SUMMARY];
+const VALIDATE_OUTPUTS: &[&str] = &[OUTPUT, REJECTS];
 
 #[derive(Clone, Debug)]
 #[allow(unused)]
 pub struct Tool {
     pub id: String,
+    links: HashMap<&'static str, usize>,
     action: Action,
 }
 impl Tool {
-    pub fn new(plan: &plans::Tool) -> Tool
+    pub fn new(plan: &plans::Tool, macros: &Macros) -> Result<Tool>
     {
         use plans::{Import, Export, Tool::*};
 
         let id = plan.id();
         let action = match plan {
-            difference(_)     => Action::Difference,
-            distinct(_)       => Action::Distinct,
-            intersect(_)      => Action::Intersect,
-            filter(config)    => Action::Filter(config.into()),
-            join(config)      => Action::Join(config.into()),
-            map(config)       => Action::Map(config.into()),
-            select(config)    => Action::Select(config.into()),
-            sort(config)      => Action::Sort(config.into()),
-            summarize(config) => Action::Summarize(config.into()),
-            union(config)     => Action::Union(config.into()),
+            columns(config)     => Action::Columns(config.try_into()?),
+            dedupe(config)      => Action::Dedupe((config, macros).try_into()?),
+            diff(config)        => Action::Diff(config.into()),
+            difference(_)       => Action::Difference,
+            distinct(_)         => Action::Distinct,
+            intersect(_)        => Action::Intersect,
+            filter(config)      => Action::Filter((config, macros).try_into()?),
+            formula(config)     => Action::Formula((config, macros).try_into()?),
+            fuzzy_match(config) => Action::FuzzyMatch(config.into()),
+            join(config)        => Action::Join((config, macros).try_into()?),
+            json_parse(config)  => Action::JsonParse(config.try_into()?),
+            map(config)         => Action::Map((config, macros).try_into()?),
+            profile(config)     => Action::Profile(config.into()),
+            scd2(config)        => Action::Scd2((config, macros).try_into()?),
+            select(config)      => Action::Select(config.into()),
+            sort(config)        => Action::Sort((config, macros).try_into()?),
+            sql(config)         => Action::Query(config.into()),
+            summarize(config)   => Action::Summarize((config, macros).try_into()?),
+            union(config)       => Action::Union(config.into()),
+            unnest(config)      => Action::Unnest(config.into()),
+            validate(config)    => Action::Validate((config, macros).try_into()?),
 
             import(format) => match format {
                 Import::csv(config)     => Action::ImportCsv(config.into()),
@@ -44,7 +100,7 @@ impl Tool {
             },
         };
 
-        Tool { action, id: id.into() }
+        Ok(Tool { action, id: id.into(), links: HashMap::new() })
     }
 
     pub fn is_async(&self) -> bool
@@ -52,39 +108,123 @@ impl Tool {
         self.action.is_async()
     }
 
+    pub fn ports(&self) -> &'static [Port]
+    {
+        self.action.ports()
+    }
+
+    pub fn is_tabular(&self) -> bool
+    {
+        matches!(self.action, Action::Query(_))
+    }
+
+    // Links the named input port, or the first, returning the port and the
+    // rank of the alias used to name it.
+    pub fn connect(&mut self, input: Option<&str>) -> Result<(&'static str, usize)>
+    {
+        let ports = self.ports();
+        let found = match input {
+            Some(name) => ports.iter().find_map(|p| {
+                if p.name == name {
+                    Some((p, 0))
+                } else {
+                    p.aliases.iter().position(|&a| a == name).map(|rank| (p, rank))
+                }
+            }),
+            None => ports.first().map(|p| (p, 0)),
+        };
+        let Some((port, rank)) = found else {
+            return match input {
+                Some(name) => plan_err!("no input port '{name}'"),
+                None => plan_err!("takes no inputs"),
+            }
+        };
+
+        let links = self.links.entry(port.name).or_default();
+        if *links > 0 && !port.variadic {
+            return plan_err!("input port '{}' accepts a single link", port.name)
+        }
+        *links += 1;
+
+        Ok((port.name, rank))
+    }
+
+    // Input ports taking a single link which weren't given one, so the tool
+    // would never have all its inputs.
+    pub fn unlinked(&self) -> impl Iterator<Item = &'static str> + '_
+    {
+        self.ports().iter()
+            .filter(|port| !port.variadic && !self.links.contains_key(port.name))
+            .map(|port| port.name)
+    }
+
+    pub fn output(&self, name: Option<&str>) -> Result<&'static str>
+    {
+        let outputs = self.action.outputs();
+        let output = match name {
+            Some(name) => outputs.iter().find(|&&o| o == name),
+            None => outputs.first(),
+        };
+
+        match (output, name) {
+            (Some(output), _) => Ok(*output),
+            (None, Some(name)) => plan_err!("no output port '{name}'"),
+            (None, None) => plan_err!("has no outputs"),
+        }
+    }
+
     pub fn is_ready(&self, data: &ToolData) -> bool
     {
-        let needed = self.action.frames();
-    
-        needed == 0 ||
-        needed == 1 && data.left.is_some() ||
-        needed == 2 && data.left.is_some() && data.right.is_some()
+        self.ports().iter()
+            .all(|port| {
+                let links = self.links.get(port.name).copied().unwrap_or(0);
+                links > 0 && data.count(port.name) == links
+            })
     }
 
-    pub fn run_sync(&self, data: Option<ToolData>) -> Result<Option<DataFrame>>
+    pub fn run_sync(&self, data: Option<ToolData>) -> Result<Output>
     {
         self.action.run_sync(data)
     }
 
-    pub async fn run_async(&self, ctx: SessionContext, data: Option<ToolData>) -> Result<Option<DataFrame>>
+    pub async fn run_async(&self, ctx: SessionContext, data: Option<ToolData>) -> Result<Output>
     {
         self.action.run_async(ctx, data).await
     }
+
+    // Builds the tool's outputs without processing any data, for checking
+    // a plan before it runs.
+    pub async fn check(&self, ctx: SessionContext, data: Option<ToolData>) -> Result<Output>
+    {
+        self.action.check(ctx, data).await
+    }
 }
 
 #[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Action {
     // Data
+    Columns(ColumnsConfig),
+    Dedupe(DedupeConfig),
+    Diff(DiffConfig),
     Difference,
     Distinct,
     Intersect,
     Filter(FilterConfig),
+    Formula(FormulaConfig),
+    FuzzyMatch(FuzzyMatchConfig),
     Join(JoinConfig),
+    JsonParse(JsonParseConfig),
     Map(MapConfig),
+    Profile(ProfileConfig),
+    Query(QueryConfig),
+    Scd2(Scd2Config),
     Select(SelectConfig),
     Sort(SortConfig),
     Summarize(SummarizeConfig),
     Union(UnionConfig),
+    Unnest(UnnestConfig),
+    Validate(ValidateConfig),
 
     // Import
     ImportCsv(CsvImportConfig),
@@ -97,16 +237,38 @@ pub enum Action {
     ExportParquet(ParquetExportConfig),
 }
 impl Action {
-    fn frames(&self) -> i8
+    fn ports(&self) -> &'static [Port]
+    {
+        use Action::*;
+
+        match self {
+            Columns(_) | Dedupe(_) | Distinct | Filter(_) | Formula(_)
+                | JsonParse(_) | Map(_) | Profile(_) | Select(_) | Sort(_)
+                | Summarize(_) | Unnest(_) | Validate(_) => ONE_PORT,
+            Diff(_) | Difference | Intersect | Join(_) | Scd2(_) => TWO_PORTS,
+            FuzzyMatch(_) | Query(_) => MANY_PORTS,
+            Union(_) => UNION_PORTS,
+            ImportCsv(_) | ImportAvro(_) | ImportParquet(_) => NO_PORTS,
+            ExportCsv(_) | ExportJson(_) | ExportParquet(_) => ONE_PORT,
+        }
+    }
+
+    fn outputs(&self) -> &'static [&'static str]
     {
         use Action::*;
 
         match self {
-            Distinct | Filter(_) | Map(_) | Select(_)
-                | Sort(_) | Summarize(_) => 1,
-            Difference | Intersect | Join(_) | Union(_) => 2,
-            ImportCsv(_) | ImportAvro(_) | ImportParquet(_) => 0,
-            ExportCsv(_) | ExportJson(_) | ExportParquet(_) => 1,
+            Dedupe(_)   => DEDUPE_OUTPUTS,
+            Diff(_)     => DIFF_OUTPUTS,
+            Filter(_)   => FILTER_OUTPUTS,
+            Join(_)     => JOIN_OUTPUTS,
+            Validate(_) => VALIDATE_OUTPUTS,
+            Columns(_) | Difference | Distinct | Formula(_) | FuzzyMatch(_)
+                | Intersect | JsonParse(_) | Map(_) | Profile(_) | Query(_)
+                | Scd2(_) | Select(_) | Sort(_) | Summarize(_) | Union(_)
+                | Unnest(_) => ONE_OUTPUT,
+            ImportCsv(_) | ImportAvro(_) | ImportParquet(_) => ONE_OUTPUT,
+            ExportCsv(_) | ExportJson(_) | ExportParquet(_) => NO_OUTPUTS,
         }
     }
 
@@ -115,15 +277,17 @@ impl Action {
         use Action::*;
 
         match self {
-            Difference | Distinct | Intersect
-                | Filter(_) | Join(_) | Map(_)
-                | Select(_) | Sort(_) | Summarize(_) | Union(_) => false,
+            Columns(_) | Dedupe(_) | Diff(_) | Difference | Distinct
+                | Intersect | Filter(_) | Formula(_) | Join(_) | JsonParse(_)
+                | Map(_) | Scd2(_) | Select(_) | Sort(_) | Summarize(_)
+                | Union(_) | Unnest(_) => false,
             ImportCsv(_) | ImportAvro(_) | ImportParquet(_) => true,
+            FuzzyMatch(_) | Profile(_) | Query(_) | Validate(_) => true,
             ExportCsv(_) | ExportJson(_) | ExportParquet(_) => true
         }
     }
 
-    async fn run_async(&self, ctx: SessionContext, data: Option<ToolData>) -> Result<Option<DataFrame>>
+    async fn run_async(&self, ctx: SessionContext, data: Option<ToolData>) -> Result<Output>
     {
         use Action::*;
 
@@ -132,29 +296,55 @@ impl Action {
             ImportCsv(config)     => read_csv(ctx, config).await,
             ImportAvro(config)    => read_avro(ctx, config).await,
             ImportParquet(config) => read_parquet(ctx, config).await,
+            Query(config)         => query(ctx, &mut data, config).await,
+            FuzzyMatch(config)    => fuzzy_match(ctx, &mut data, config).await,
+            Profile(config)       => profile(ctx, &mut data, config).await,
+            Validate(config)      => validate(&mut data, config).await,
             ExportCsv(config)     => write_csv(&mut data, config).await,
             ExportJson(config)    => write_json(&mut data, config).await,
-            ExportParquet(config) => write_parquet(&mut data, config).await,
+            ExportParquet(config) => write_parquet(ctx, &mut data, config).await,
             _ => panic!("Sync tool running async")
         }
     }
 
-    fn run_sync(&self, data: Option<ToolData>) -> Result<Option<DataFrame>>
+    async fn check(&self, ctx: SessionContext, data: Option<ToolData>) -> Result<Output>
+    {
+        use Action::*;
+
+        let mut data = data.unwrap_or_default();
+        match self {
+            FuzzyMatch(config) => fuzzy_match_outputs(ctx, config),
+            Profile(_)         => profile_outputs(ctx),
+            Validate(_)        => validate_outputs(&mut data),
+            ExportCsv(_) | ExportJson(_) | ExportParquet(_) => Ok(vec![]),
+            _ if self.is_async() => self.run_async(ctx, Some(data)).await,
+            _ => self.run_sync(Some(data)),
+        }
+    }
+
+    fn run_sync(&self, data: Option<ToolData>) -> Result<Output>
     {
         use Action::*;
 
         let mut data = data.unwrap();
         match self {
+            Columns(config)   => columns(&mut data, config),
+            Dedupe(config)    => dedupe(&mut data, config),
+            Diff(config)      => diff(&mut data, config),
             Difference        => difference(&mut data),
             Distinct          => distinct(&mut data),
             Intersect         => intersect(&mut data),
             Filter(config)    => filter(&mut data, config),
+            Formula(config)   => formula(&mut data, config),
             Join(config)      => join(&mut data, config),
+            JsonParse(config) => json_parse(&mut data, config),
             Map(config)       => project(&mut data, config),
+            Scd2(config)      => scd2(&mut data, config),
             Select(config)    => select(&mut data, config),
             Sort(config)      => sort(&mut data, config),
             Summarize(config) => summarize(&mut data, config),
             Union(config)     => union(&mut data, config),
+            Unnest(config)    => unnest(&mut data, config),
             _ => panic!("Async tool running sync")
         }
     }
@@ -0,0 +1,173 @@
+use std::any::Any;
+use std::fmt;
+use std::fs;
+use std::sync::Mutex;
+
+use datafusion::arrow::array::new_empty_array;
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::DataType;
+use datafusion::common::{exec_datafusion_err, plan_datafusion_err, plan_err, ScalarValue};
+use datafusion::error::Result;
+use datafusion::logical_expr::{ColumnarValue, ScalarUDF, ScalarUDFImpl, Signature, Volatility};
+use wasmi::core::{ValType, F32, F64};
+use wasmi::{Config, Engine, Func, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, Val};
+
+use crate::plans::{Udf, WasmType};
+
+// Plugins run in an interpreter with nothing linked in, so a module has no
+// imports through which to reach the filesystem, network or clock. Fuel
+// and memory limits stop a call from running away.
+const FUEL: u64 = 10_000_000;
+const MEMORY: usize = 64 << 20;
+
+pub fn load(udf: &Udf) -> Result<ScalarUDF>
+{
+    Ok(ScalarUDF::new_from_impl(WasmUdf::new(udf)?))
+}
+
+struct Instance {
+    store: Store<StoreLimits>,
+    func: Func,
+}
+
+pub struct WasmUdf {
+    name: String,
+    args: Vec<WasmType>,
+    returns: WasmType,
+    signature: Signature,
+    instance: Mutex<Instance>,
+}
+impl fmt::Debug for WasmUdf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "WasmUdf({}: {:?} -> {:?})", self.name, self.args, self.returns)
+    }
+}
+
+impl WasmUdf {
+    fn new(udf: &Udf) -> Result<Self>
+    {
+        let name = udf.name;
+        let bytes = fs::read(udf.module)
+            .map_err(|e| plan_datafusion_err!("udf {name}: cannot read {}: {e}", udf.module))?;
+
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &bytes)
+            .map_err(|e| plan_datafusion_err!("udf {name}: {e}"))?;
+        if let Some(import) = module.imports().next() {
+            return plan_err!(
+                "udf {name}: module imports {}::{}, plugins may not import anything",
+                import.module(), import.name()
+            )
+        }
+
+        let limits = StoreLimitsBuilder::new().memory_size(MEMORY).build();
+        let mut store = Store::new(&engine, limits);
+        store.limiter(|limits| limits);
+        let instance = Linker::new(&engine)
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|e| plan_datafusion_err!("udf {name}: {e}"))?;
+
+        let export = udf.export.unwrap_or(name);
+        let Some(func) = instance.get_func(&store, export) else {
+            return plan_err!("udf {name}: module has no function '{export}'")
+        };
+        let ty = func.ty(&store);
+        let params = udf.args.iter().map(|t| val_type(*t)).collect::<Vec<_>>();
+        if ty.params() != params || ty.results() != [val_type(udf.returns)] {
+            return plan_err!(
+                "udf {name}: '{export}' has type {:?} -> {:?}, expected {:?} -> {:?}",
+                ty.params(), ty.results(), udf.args, udf.returns
+            )
+        }
+
+        let types = udf.args.iter().map(|&t| t.into()).collect();
+        Ok(Self {
+            name: name.into(),
+            args: udf.args.clone(),
+            returns: udf.returns,
+            signature: Signature::exact(types, Volatility::Immutable),
+            instance: Mutex::new(Instance { store, func }),
+        })
+    }
+
+    fn call(&self, inputs: &[Val]) -> Result<ScalarValue>
+    {
+        let mut instance = self.instance.lock().unwrap();
+        let Instance { store, func } = &mut *instance;
+        store.set_fuel(FUEL).map_err(|e| exec_datafusion_err!("udf {}: {e}", self.name))?;
+
+        let mut outputs = [Val::default(val_type(self.returns))];
+        func.call(&mut *store, inputs, &mut outputs)
+            .map_err(|e| exec_datafusion_err!("udf {}: {e}", self.name))?;
+
+        Ok(match &outputs[0] {
+            Val::I32(v) => ScalarValue::Int32(Some(*v)),
+            Val::I64(v) => ScalarValue::Int64(Some(*v)),
+            Val::F32(v) => ScalarValue::Float32(Some(v.to_float())),
+            Val::F64(v) => ScalarValue::Float64(Some(v.to_float())),
+            v => return Err(exec_datafusion_err!("udf {}: unsupported result {v:?}", self.name)),
+        })
+    }
+}
+
+impl ScalarUDFImpl for WasmUdf {
+    fn as_any(&self) -> &dyn Any { self }
+    fn name(&self) -> &str { &self.name }
+    fn signature(&self) -> &Signature { &self.signature }
+
+    fn return_type(&self, _: &[DataType]) -> Result<DataType>
+    {
+        Ok(self.returns.into())
+    }
+
+    // Rows with a null argument give null without calling the plugin.
+    fn invoke_batch(&self, args: &[ColumnarValue], rows: usize) -> Result<ColumnarValue>
+    {
+        let returns: DataType = self.returns.into();
+        let arrays = args.iter().zip(&self.args)
+            .map(|(arg, &dtype)| Ok(cast(&arg.clone().into_array(rows)?, &dtype.into())?))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut values = Vec::with_capacity(rows);
+        let mut inputs = Vec::with_capacity(arrays.len());
+        for row in 0..rows {
+            inputs.clear();
+            for array in &arrays {
+                match ScalarValue::try_from_array(array, row)? {
+                    ScalarValue::Int32(Some(v))   => inputs.push(Val::I32(v)),
+                    ScalarValue::Int64(Some(v))   => inputs.push(Val::I64(v)),
+                    ScalarValue::Float32(Some(v)) => inputs.push(Val::F32(F32::from_float(v))),
+                    ScalarValue::Float64(Some(v)) => inputs.push(Val::F64(F64::from_float(v))),
+                    _ => break,
+                }
+            }
+            values.push(if inputs.len() == arrays.len() {
+                self.call(&inputs)?
+            } else {
+                ScalarValue::try_from(&returns)?
+            });
+        }
+
+        let array = if values.is_empty() {
+            new_empty_array(&returns)
+        } else {
+            ScalarValue::iter_to_array(values)?
+        };
+
+        Ok(ColumnarValue::Array(array))
+    }
+}
+
+fn val_type(dtype: WasmType) -> ValType
+{
+    match dtype {
+        WasmType::i32 => ValType::I32,
+        WasmType::i64 => ValType::I64,
+        WasmType::f32 => ValType::F32,
+        WasmType::f64 => ValType::F64,
+    }
+}
@@ -1,19 +1,31 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 
-use datafusion::dataframe::DataFrame;
+use datafusion::common::plan_err;
 use datafusion::error::Result;
 use datafusion::execution::context::SessionContext;
 
 use petgraph::Direction::Incoming;
+use petgraph::algo::toposort;
 use petgraph::graph::{Graph, NodeIndex};
 use petgraph::visit::EdgeRef;
 use tokio::task::JoinSet;
 
-use crate::plans::{InputSide, Plan};
-use crate::tool::{Tool, ToolData};
+use crate::check::{self, CheckError};
+use crate::macros::Macros;
+use crate::plans::Plan;
+use crate::tool::{Order, Output, Tool, ToolData};
+use crate::wasm;
 
-type WorkflowGraph = Graph<Tool, InputSide>;
+type WorkflowGraph = Graph<Tool, Input>;
+
+#[derive(Clone, Debug)]
+struct Input {
+    output: &'static str,
+    port: &'static str,
+    order: Order,
+    label: String,
+}
 
 #[derive(Debug)]
 #[allow(unused)]
@@ -21,33 +33,116 @@ pub struct Workflow {
     id: String,
     name: String,
     graph: WorkflowGraph,
+    macros: Macros,
 }
 impl Workflow {
-    pub fn new(plan: &Plan) -> Workflow
+    pub fn new(plan: &Plan) -> Result<Workflow, Vec<CheckError>>
     {
         let count = plan.tools.len();
-        let mut graph  = Graph::<Tool, InputSide>::with_capacity(count, count);
-
-        let nodes = plan.tools.iter()
-            .map(|schema| {
-                let tool = Tool::new(schema);
-                let node = graph.add_node(tool);
-                (schema.id(), node)
-            })
-            .collect::<HashMap<_,_>>();
-
-        plan.links.iter()
-            .for_each(|link| {
-                let src = nodes.get(&link.src).unwrap();
-                let dst = nodes.get(&link.dst).unwrap();
-                graph.add_edge(*src, *dst, link.input);
-            });
+        let mut graph  = Graph::<Tool, Input>::with_capacity(count, count);
+        let mut errors = vec![];
+
+        let mut udfs = BTreeMap::new();
+        for (index, udf) in plan.udfs.iter().enumerate() {
+            let loaded = match udfs.contains_key(udf.name) {
+                true => plan_err!("udf '{}' is defined more than once", udf.name),
+                false => wasm::load(udf),
+            };
+            match loaded {
+                Ok(loaded) => { udfs.insert(udf.name.to_string(), loaded); },
+                Err(e) => errors.push(CheckError {
+                    tool: plan.id.into(),
+                    path: format!("$.udfs[{index}]"),
+                    message: e.strip_backtrace(),
+                }),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors)
+        }
+
+        let macros = Macros::load(&plan.functions, udfs).map_err(|e| vec![CheckError {
+            tool: plan.id.into(),
+            path: "$.functions".into(),
+            message: e.strip_backtrace(),
+        }])?;
+
+        let mut nodes = HashMap::new();
+        for (index, schema) in plan.tools.iter().enumerate() {
+            let found = check::expressions(index, schema, &macros);
+            if !found.is_empty() {
+                errors.extend(found);
+                continue
+            }
+            match Tool::new(schema, &macros) {
+                Ok(tool) => { nodes.insert(schema.id(), graph.add_node(tool)); },
+                Err(e) => errors.push(CheckError {
+                    tool: schema.id().into(),
+                    path: format!("$.tools[{index}]"),
+                    message: e.strip_backtrace(),
+                }),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors)
+        }
+
+        for (order, link) in plan.links.iter().enumerate() {
+            let path = format!("$.links[{order}]");
+            let error = |tool: &str, path: String, message: String| CheckError {
+                tool: tool.into(), path, message
+            };
+            let (Some(&src), Some(&dst)) = (nodes.get(&link.src), nodes.get(&link.dst)) else {
+                for (name, id) in [("src", link.src), ("dst", link.dst)] {
+                    if !nodes.contains_key(&id) {
+                        let message = format!("unknown tool '{id}'");
+                        errors.push(error(id, format!("{path}.{name}"), message));
+                    }
+                }
+                continue
+            };
+
+            let output = graph[src].output(link.output)
+                .map_err(|e| error(link.src, format!("{path}.output"), e.strip_backtrace()));
+            let input = graph[dst].connect(link.input)
+                .map_err(|e| error(link.dst, format!("{path}.input"), e.strip_backtrace()));
+            if link.table.is_none() && graph[dst].is_tabular() {
+                let message = "links into a sql tool require a table name".into();
+                errors.push(error(link.dst, format!("{path}.table"), message));
+            }
+            match (output, input) {
+                (Ok(output), Ok((port, rank))) => {
+                    let label = link.table.unwrap_or(link.src).into();
+                    let input = Input { output, port, order: (rank, order), label };
+                    graph.add_edge(src, dst, input);
+                },
+                (output, input) => {
+                    errors.extend(output.err());
+                    errors.extend(input.err());
+                },
+            }
+        }
+
+        for (index, tool) in graph.node_weights().enumerate() {
+            for port in tool.unlinked() {
+                errors.push(CheckError {
+                    tool: tool.id.clone(),
+                    path: format!("$.tools[{index}]"),
+                    message: format!("input port '{port}' has no link"),
+                });
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors)
+        }
 
-        Workflow { 
+        Ok(Workflow {
             id: plan.id.into(),
             name: plan.name.into(),
             graph,
-        }
+            macros,
+        })
     }
 
     pub async fn run(&self, debug: u8) -> Result<(), Box<dyn Error>>
@@ -65,7 +160,7 @@ impl Workflow {
                 }
                 let data = dfs.remove(&ix);
                 if self.graph[ix].is_async() {
-                    let ctx  = SessionContext::new();
+                    let ctx  = self.session();
                     let tool = self.graph[ix].clone();
                     async_tools.spawn(async move { run_async(ix, ctx, tool, data).await });
                 } else {
@@ -80,24 +175,92 @@ impl Workflow {
                 results.push(res.unwrap().unwrap());
             }
 
-            for (ix, opt) in results {
-                if let Some(df) = opt {
-                    self.graph.edges(ix)
-                        .map(|edge| (edge.target(), *edge.weight()))
-                        .for_each(|(node, side)| {
-                            let data = dfs.entry(node).or_default();
-                            data.set(side, df.clone());
-                            if self.graph[node].is_ready(data) {
-                                ready.push_back(node)
-                            }
-                        });
-                }
+            for (ix, outputs) in results {
+                ready.extend(self.route(ix, &outputs, &mut dfs));
             }
         }
 
         Ok(())
     }
 
+    // Walks the plan in dependency order, checking each tool against the
+    // schemas of its inputs. Tools downstream of a failure are skipped.
+    pub async fn check(&self, plan: &Plan<'_>) -> Vec<CheckError>
+    {
+        let mut errors = vec![];
+        let mut dfs: HashMap<NodeIndex, ToolData> = HashMap::new();
+        let order = match toposort(&self.graph, None) {
+            Ok(order) => order,
+            Err(cycle) => return vec![CheckError {
+                tool: self.graph[cycle.node_id()].id.clone(),
+                path: "$.links".into(),
+                message: "the links form a cycle through this tool".into(),
+            }],
+        };
+        for ix in order {
+            let tool = &self.graph[ix];
+            let data = dfs.remove(&ix).unwrap_or_default();
+            if !tool.is_ready(&data) {
+                continue
+            }
+
+            let found = check::tool(ix.index(), &plan.tools[ix.index()], &data, &self.macros);
+            if !found.is_empty() {
+                errors.extend(found);
+                continue
+            }
+            // Types are only coerced by the analyzer, so run it on each output
+            // here rather than have a mismatch panic the run.
+            let checked = tool.check(self.session(), Some(data)).await.and_then(|outputs| {
+                outputs.iter().try_for_each(|(_, df)| df.clone().into_optimized_plan().map(drop))?;
+                Ok(outputs)
+            });
+            match checked {
+                Ok(outputs) => { self.route(ix, &outputs, &mut dfs); },
+                Err(e) => errors.push(CheckError {
+                    tool: tool.id.clone(),
+                    path: format!("$.tools[{}]", ix.index()),
+                    message: e.strip_backtrace(),
+                }),
+            }
+        }
+
+        errors
+    }
+
+    // Hands a tool's outputs to the tools linked to it, returning those
+    // which now have all their inputs.
+    fn route(
+        &self,
+        ix: NodeIndex,
+        outputs: &Output,
+        dfs: &mut HashMap<NodeIndex, ToolData>
+    ) -> Vec<NodeIndex>
+    {
+        let mut ready = vec![];
+        self.graph.edges(ix)
+            .map(|edge| (edge.target(), edge.weight()))
+            .for_each(|(node, input)| {
+                let found = outputs.iter().find(|(name, _)| *name == input.output);
+                if let Some((_, df)) = found {
+                    let data = dfs.entry(node).or_default();
+                    data.add(input.port, input.order, &input.label, df.clone());
+                    if self.graph[node].is_ready(data) {
+                        ready.push(node)
+                    }
+                }
+            });
+
+        ready
+    }
+
+    fn session(&self) -> SessionContext
+    {
+        let ctx = SessionContext::new();
+        self.macros.udfs().for_each(|udf| ctx.register_udf(udf.clone()));
+
+        ctx
+    }
 }
 
 async fn run_async(
@@ -105,8 +268,8 @@ async fn run_async(
     ctx: SessionContext,
     tool: Tool,
     data: Option<ToolData>
-) -> Result<(NodeIndex, Option<DataFrame>)>
+) -> Result<(NodeIndex, Output)>
 {
     let res = tool.run_async(ctx, data).await?;
     Ok((ix, res))
-}
\ No newline at end of file
+}
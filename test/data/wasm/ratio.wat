@@ -0,0 +1,7 @@
+;; Source for ratio.wasm, used by test/plans/udf.json as power_to_weight.
+;; Rebuild with: wat2wasm ratio.wat -o ratio.wasm
+(module
+  (func (export "power_w") (param f64 f64) (result f64)
+    local.get 0
+    local.get 1
+    f64.div))